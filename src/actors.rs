@@ -1,53 +1,196 @@
-use avian2d::prelude::{CollisionEnded, LinearVelocity};
+use avian2d::prelude::{AngularVelocity, Collisions, LinearVelocity, Rotation};
 use bevy::{
     app::{App, FixedUpdate, PreUpdate},
     ecs::schedule::IntoSystemConfigs,
+    input::InputSystem,
     math::{vec2, Vec2, Vec3Swizzles},
-    prelude::{ButtonInput, Entity, EventReader, KeyCode, Plugin, Query, Res, Transform, With},
+    prelude::{
+        in_state, not, resource_exists, Camera, Entity, EventWriter, GlobalTransform, Or, Plugin,
+        Query, Res, ResMut, Resource, Touches, Transform, Window, With, Without,
+    },
+    time::{Fixed, Time},
+    window::PrimaryWindow,
 };
 
-use crate::structure::*;
+use crate::{
+    ai::{AiController, PaddleView},
+    controls::{InputMap, PlayerInput},
+    netcode::NetSession,
+    replay::Playback,
+    rules::GameRules,
+    structure::*,
+};
 
 pub struct PongActorPlugin;
 
 impl Plugin for PongActorPlugin {
     fn build(&self, app: &mut App) {
+        app.add_event::<PaddleHit>();
+        app.init_resource::<TickInput>();
+        app.init_resource::<PointerTarget>();
         app.add_systems(
             PreUpdate,
-            ((handle_player_input, ai_movement), move_paddles).chain(),
+            (
+                sample_player_input,
+                sample_pointer_input.run_if(|input_map: Res<InputMap>| input_map.pointer_control),
+            )
+                .chain()
+                .after(InputSystem)
+                .run_if(in_state(PauseState::Running))
+                .run_if(not(resource_exists::<Playback>))
+                .run_if(not(resource_exists::<ExternalInput>)),
+        );
+        app.add_systems(
+            FixedUpdate,
+            (
+                (
+                    steer_to_pointer
+                        .run_if(|input_map: Res<InputMap>| input_map.pointer_control)
+                        .run_if(not(resource_exists::<ExternalInput>))
+                        .run_if(not(resource_exists::<NetSession>)),
+                    apply_player_input,
+                    ai_movement,
+                )
+                    .chain()
+                    .in_set(GameplaySet::Input)
+                    .run_if(not(resource_exists::<Playback>)),
+                move_paddles.in_set(GameplaySet::Movement),
+            )
+                .run_if(not(in_state(MatchState::GameOver))),
         );
-        app.add_systems(FixedUpdate, (unstick_ball, reflect_ball));
+        app.add_systems(
+            FixedUpdate,
+            (unstick_ball, reflect_ball, curve_ball)
+                .chain()
+                .in_set(GameplaySet::Ball)
+                .run_if(in_state(MatchState::Playing)),
+        );
+        app.add_systems(FixedUpdate, clear_serve.after(GameplaySet::Phase));
+    }
+}
+
+// Record up/down input from each side's keys or gamepad for the next tick, and hold on to
+// serve presses until a tick has seen them
+pub fn sample_player_input(input: PlayerInput, mut tick_input: ResMut<TickInput>) {
+    for side in [Side::Left, Side::Right] {
+        let sampled = tick_input.side_mut(side);
+        sampled.movement = input.movement(side);
+        sampled.serve |= input.just_pressed(side, Action::Serve);
     }
 }
 
-// Check for up/down input from player
-fn handle_player_input(
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut paddle: Query<&mut Velocity, With<Player>>,
+// Height player one's paddle is following the pointer to, while they aren't using keys or a pad
+#[derive(Resource, Default)]
+pub struct PointerTarget(pub Option<f32>);
+
+// Steer player one's paddle towards the cursor or touch point, unless they're using keys or a
+// pad. Velocity is capped at what a held key would give so pointer players aren't any faster
+pub fn sample_pointer_input(
+    touches: Res<Touches>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform)>,
+    paddles: Query<(&Transform, &Side), With<Player>>,
+    mut tick_input: ResMut<TickInput>,
+    mut pointer_target: ResMut<PointerTarget>,
+    rules: Res<GameRules>,
 ) {
-    if let Ok(mut velocity) = paddle.get_single_mut() {
-        if keyboard_input.pressed(KeyCode::ArrowUp) {
-            velocity.0.y = 1.;
-        } else if keyboard_input.pressed(KeyCode::ArrowDown) {
-            velocity.0.y = -1.;
-        } else {
-            velocity.0.y = 0.;
+    pointer_target.0 = None;
+    if tick_input.right.movement != 0. {
+        return;
+    }
+
+    let (Ok(window), Ok((camera, camera_transform))) = (window.get_single(), camera.get_single())
+    else {
+        return;
+    };
+
+    // Touches take priority, since a touchscreen can also report a stale cursor
+    let Some(pointer) = touches
+        .iter()
+        .next()
+        .map(|touch| touch.position())
+        .or_else(|| window.cursor_position())
+        .and_then(|pointer| camera.viewport_to_world_2d(camera_transform, pointer))
+    else {
+        return;
+    };
+
+    let max_y = rules.paddle_max_y();
+    let target_y = pointer.y.clamp(-max_y, max_y);
+    pointer_target.0 = Some(target_y);
+
+    // Online play sends this on as the input for a tick, everywhere else steer_to_pointer works
+    // it out again for each tick
+    for (transform, side) in &paddles {
+        if *side == Side::Right {
+            tick_input.right.movement = pointer_movement(target_y, transform.translation.y, &rules);
+        }
+    }
+}
+
+// A slow frame can run several ticks, and movement worked out once for the frame would carry
+// the paddle past the pointer. So go from where the paddle is at the start of every tick
+fn steer_to_pointer(
+    pointer_target: Res<PointerTarget>,
+    paddles: Query<(&Transform, &Side), With<Player>>,
+    mut tick_input: ResMut<TickInput>,
+    rules: Res<GameRules>,
+) {
+    let Some(target_y) = pointer_target.0 else {
+        return;
+    };
+
+    for (transform, side) in &paddles {
+        if *side == Side::Right {
+            tick_input.right.movement = pointer_movement(target_y, transform.translation.y, &rules);
         }
     }
 }
 
+fn pointer_movement(target_y: f32, paddle_y: f32, rules: &GameRules) -> f32 {
+    ((target_y - paddle_y) / rules.paddle_speed).clamp(-1., 1.)
+}
+
+// Drive each player's and bot's paddle from this tick's input
+#[allow(clippy::type_complexity)]
+pub fn apply_player_input(
+    tick_input: Res<TickInput>,
+    mut paddles: Query<(&mut Velocity, &Side), Or<(With<Player>, With<Bot>)>>,
+) {
+    for (mut velocity, side) in &mut paddles {
+        velocity.0.y = tick_input.side(*side).movement;
+    }
+}
+
+// Serve presses only count for the tick that saw them
+fn clear_serve(mut tick_input: ResMut<TickInput>) {
+    tick_input.left.serve = false;
+    tick_input.right.serve = false;
+}
+
 // Calculate AI movement direction (if any)
 fn ai_movement(
-    mut ai: Query<(&mut Velocity, &Position), With<Ai>>,
-    ball: Query<&Position, With<Ball>>,
+    mut ai: Query<(&mut Velocity, &Position, &Side, &mut AiController), With<Ai>>,
+    paddles: Query<(&Position, &Side), With<Paddle>>,
+    ball: Query<(&Position, &LinearVelocity), With<Ball>>,
+    rules: Res<GameRules>,
+    time: Res<Time>,
 ) {
-    if let Ok((mut velocity, position)) = ai.get_single_mut() {
-        if let Ok(ball_position) = ball.get_single() {
-            let a_to_b = ball_position.0 - position.0;
-            let accel = exp_easeout(a_to_b.y / WIN_HEIGHT, AI_SKILL);
+    if let Ok((ball_position, ball_velocity)) = ball.get_single() {
+        for (mut velocity, position, side, mut controller) in &mut ai {
+            let opponent = paddles
+                .iter()
+                .find(|(_, other_side)| *other_side != side)
+                .map_or(Vec2::new(-position.0.x, 0.), |(other, _)| other.0);
 
-            // If the AI paddle is above the ball, move down, if it's below move up
-            velocity.0.y = accel;
+            let view = PaddleView {
+                paddle: position.0,
+                opponent,
+                ball: ball_position.0,
+                ball_velocity: ball_velocity.0,
+            };
+
+            velocity.0.y = controller.0.update(&view, &rules, time.delta_seconds());
         }
     }
 }
@@ -59,67 +202,176 @@ fn ai_movement(
 //     }
 // }
 
+// Bounce the ball off any paddle it touched in the last physics step. Paddles are sensors, so
+// this is the only thing that sends the ball away from them:
+// - Face hits, with the ball's center alongside the paddle, send it back at an angle set by how
+//   far from the middle of the paddle it hit (see paddle_hit_velocity), carrying on up or down
+//   the way it was going. A moving paddle adds paddle_english of its speed to that, and spins
+//   the ball as if its face had dragged the ball's edge along by the same amount
+// - End cap and corner hits bounce it off the contact normal like a wall would, as seen from the
+//   paddle, so a paddle moving into the ball knocks it along. The speed it leaves the paddle
+//   at is capped at 1.5 times the ball speed, as fast as a face hit can send it
+// Both go faster as the rally goes up speed tiers
+// Contacts with a ball that's already moving away are left alone, so a hit that overlaps for a
+// few ticks is only handled once. Only hits that send the ball back into the field count as a
+// PaddleHit, and towards the rally
+#[allow(clippy::type_complexity)]
 fn reflect_ball(
-    mut ball: Query<(Entity, &mut LinearVelocity, &Position), With<Ball>>,
-    paddle: Query<(Entity, &Position), With<Paddle>>,
-    mut events: EventReader<CollisionEnded>,
+    mut ball: Query<
+        (
+            Entity,
+            &mut LinearVelocity,
+            &mut AngularVelocity,
+            &mut Transform,
+        ),
+        With<Ball>,
+    >,
+    paddles: Query<(&Transform, &Rotation, &Velocity, &Side), (With<Paddle>, Without<Ball>)>,
+    collisions: Res<Collisions>,
+    mut hits: EventWriter<PaddleHit>,
+    mut rally: ResMut<Rally>,
+    rules: Res<GameRules>,
+    time: Res<Time<Fixed>>,
 ) {
-    // Get a list of collisions since the last FixedUpdate and the entities involved
-    for CollisionEnded(entity1, entity2) in events.read() {
-        if let Ok((entity_ball, mut ball_vel, ball_pos)) = ball.get_single_mut() {
-            for (entity_paddle, paddle_pos) in paddle.iter() {
-                // I am aware this is a cursed abomination that can probably be better
-                // Check if the two entities involved were a ball and a paddle
-                if (entity1.index() == entity_ball.index()
-                    || entity2.index() == entity_ball.index())
-                    && (entity1.index() == entity_paddle.index()
-                        || entity2.index() == entity_paddle.index())
-                {
-                    // How far from the center of the paddle did the ball hit? (0 = center, 25 = corner pixel)
-                    let dist_from_center = (paddle_pos.0.y - ball_pos.0.y).abs();
-
-                    // Remap that to a hit velocity between BALL_SPEED and 1.5 times BALL_SPEED
-                    let scaled_dist = f32_map(
-                        0.,
-                        PADDLE_HEIGHT / 2.,
-                        BALL_SPEED,
-                        BALL_SPEED * 1.5,
-                        dist_from_center,
-                    );
-
-                    // Convert that to a full velocity, respecting conservation of energy by scaling down the horizontal velocity accordingly
-                    // This makes the hit behavior feel less weird
-                    let hit_velocity =
-                        vec2((BALL_SPEED * 2.) - scaled_dist, scaled_dist) * ball_vel.signum();
-
-                    // Apply that velocity to the ball
-                    ball_vel.0 = hit_velocity;
-                }
-            }
+    let Ok((entity_ball, mut ball_velocity, mut ball_spin, mut ball_transform)) =
+        ball.get_single_mut()
+    else {
+        return;
+    };
+
+    for contacts in collisions.collisions_with_entity(entity_ball) {
+        if !contacts.during_current_frame {
+            continue;
+        }
+
+        // The collision layers only let the ball touch paddles and gutters, so anything else in
+        // the pair that's a paddle is the one it hit
+        let paddle_is_first = contacts.entity1 != entity_ball;
+        let entity_paddle = if paddle_is_first {
+            contacts.entity1
+        } else {
+            contacts.entity2
+        };
+        let Ok((paddle_transform, rotation, paddle_velocity, side)) = paddles.get(entity_paddle)
+        else {
+            continue;
+        };
+
+        // Deepest point of contact, with its normal pointing out of the paddle towards the ball
+        let Some(contact) = contacts
+            .manifolds
+            .iter()
+            .flat_map(|manifold| &manifold.contacts)
+            .max_by(|a, b| a.penetration.total_cmp(&b.penetration))
+        else {
+            continue;
+        };
+        let normal = if paddle_is_first {
+            contact.global_normal1(rotation)
+        } else {
+            contact.global_normal2(rotation)
+        };
+
+        // Work in the paddle's frame. Velocity is how far it actually moved this tick, in
+        // paddle_speed units
+        let paddle_motion = paddle_velocity.0 * rules.paddle_speed / time.timestep().as_secs_f32();
+        let relative = ball_velocity.0 - paddle_motion;
+        if relative.dot(normal) >= 0. {
+            continue;
         }
+
+        // Sensors don't push back, so take the ball out of the paddle ourselves
+        ball_transform.translation += (normal * contact.penetration).extend(0.);
+
+        let paddle_center = paddle_transform.translation.xy();
+        let offset = ball_transform.translation.xy() - paddle_center;
+
+        let speed_up = rally.speed_multiplier(&rules);
+        let mut new_velocity = if offset.y.abs() <= rules.paddle_height / 2. {
+            // The ball's edge touching the paddle is opposite the normal, so this is how fast
+            // the paddle is moving along it, anticlockwise
+            let english = paddle_motion.dot((-normal).perp()) * rules.paddle_english;
+            ball_spin.0 = english / rules.ball_size;
+
+            paddle_hit_velocity(offset.y.abs(), vec2(offset.x, ball_velocity.0.y), &rules)
+                * speed_up
+                + vec2(0., paddle_motion.y * rules.paddle_english)
+        } else {
+            let reflected = relative - 2. * relative.dot(normal) * normal;
+            paddle_motion + reflected.clamp_length_max(rules.ball_speed * 1.5 * speed_up)
+        };
+
+        // Paddles sit either side of the middle, so heading back in is heading towards x = 0
+        if new_velocity.x * paddle_center.x < 0. {
+            hits.send(PaddleHit(*side));
+
+            // A return that takes the rally up a tier goes out at the new speed
+            rally.hits += 1;
+            new_velocity *= rally.speed_multiplier(&rules) / speed_up;
+        }
+        ball_velocity.0 = new_velocity;
     }
 }
 
-fn unstick_ball(mut ball: Query<&mut LinearVelocity, With<Ball>>) {
+// Velocity of the ball after a paddle hit dist_from_center away from the middle of the paddle
+// direction is the ball's velocity after bouncing, only its signs are kept
+pub fn paddle_hit_velocity(dist_from_center: f32, direction: Vec2, rules: &GameRules) -> Vec2 {
+    // Remap the distance to a vertical speed between the ball speed and 1.5 times the ball speed
+    // Corner hits can put the ball's center past the end of the paddle, so cap it there
+    let scaled_dist = f32_map(
+        0.,
+        rules.paddle_height / 2.,
+        rules.ball_speed,
+        rules.ball_speed * 1.5,
+        dist_from_center.min(rules.paddle_height / 2.),
+    );
+
+    // Convert that to a full velocity, respecting conservation of energy by scaling down the horizontal velocity accordingly
+    // This makes the hit behavior feel less weird
+    vec2((rules.ball_speed * 2.) - scaled_dist, scaled_dist) * direction.signum()
+}
+
+// Bend a spinning ball's path sideways, like the Magnus effect. Only its direction changes, so
+// spin never speeds the ball up or slows it down
+fn curve_ball(
+    mut ball: Query<(&mut LinearVelocity, &AngularVelocity), With<Ball>>,
+    rules: Res<GameRules>,
+    time: Res<Time>,
+) {
+    if let Ok((mut velocity, spin)) = ball.get_single_mut() {
+        let speed = velocity.0.length();
+        let curve = velocity.0.perp() * spin.0 * rules.magnus_curve;
+        velocity.0 = (velocity.0 + curve * time.delta_seconds()).normalize_or_zero() * speed;
+    }
+}
+
+fn unstick_ball(mut ball: Query<&mut LinearVelocity, With<Ball>>, rules: Res<GameRules>) {
     if let Ok(mut velocity) = ball.get_single_mut() {
         // If the player or AI moves weirdly the ball can get pinched between the paddle and the bumper
         // This detects if the horizontal and vertical velocity get below 10, and resets them to default just in case
-        if velocity.0.x < 10. && velocity.0.y < 10. {
+        if velocity.0.x.abs() < 10. && velocity.0.y.abs() < 10. {
             velocity.0 = Vec2::new(
-                BALL_SPEED * velocity.x.signum(),
-                BALL_SPEED * velocity.y.signum(),
+                rules.ball_speed * velocity.x.signum(),
+                rules.ball_speed * velocity.y.signum(),
             );
         }
     }
 }
 
-fn move_paddles(mut paddle: Query<(&mut Transform, &Velocity), With<Paddle>>) {
-    let max_y = WIN_HEIGHT / 2. - GUTTER_HEIGHT - PADDLE_HEIGHT / 2.;
+fn move_paddles(
+    mut paddle: Query<(&mut Transform, &mut Velocity), With<Paddle>>,
+    rules: Res<GameRules>,
+) {
+    let max_y = rules.paddle_max_y();
 
-    for (mut transform, velocity) in &mut paddle {
-        let new_position = transform.translation.xy() + velocity.0 * PADDLE_SPEED;
-        if new_position.y.abs() < max_y {
-            transform.translation = new_position.extend(0.);
-        }
+    // Stop right at the gutters, so a paddle can always reach anything the ball can
+    // Velocity is left as how far the paddle really went, so reflect_ball doesn't put english
+    // on the ball from a paddle pushing against a gutter
+    for (mut transform, mut velocity) in &mut paddle {
+        let old_position = transform.translation.xy();
+        let new_position = old_position + velocity.0 * rules.paddle_speed;
+        let new_position = vec2(new_position.x, new_position.y.clamp(-max_y, max_y));
+        transform.translation = new_position.extend(0.);
+        velocity.0 = (new_position - old_position) / rules.paddle_speed;
     }
 }
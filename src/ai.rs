@@ -0,0 +1,310 @@
+use bevy::{math::Vec2, prelude::Component};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actors::paddle_hit_velocity,
+    rules::GameRules,
+    structure::{exp_easeout, f32_map, GameRng},
+};
+
+// ##############################################################
+// # Controller Interface
+// ##############################################################
+
+// Everything an AI gets to know about the field each frame
+pub struct PaddleView {
+    pub paddle: Vec2,        // Center of the paddle being controlled
+    pub opponent: Vec2,      // Center of the other paddle
+    pub ball: Vec2,          // Center of the ball
+    pub ball_velocity: Vec2, // Ball LinearVelocity
+}
+
+// Strategy deciding how an AI paddle moves
+pub trait PaddleController: Send + Sync + 'static {
+    // Returns the paddle velocity to use this frame, -1 to 1 is the same range a player has
+    fn update(&mut self, view: &PaddleView, rules: &GameRules, delta: f32) -> f32;
+
+    // Adjust to a difficulty between 0 (easiest) and 1 (hardest), used by adaptive difficulty
+    // Controllers that don't support it ignore it, so adaptive difficulty has no effect on them
+    fn set_difficulty(&mut self, _level: f32) {}
+}
+
+// Holds the strategy driving an Ai paddle
+#[derive(Component)]
+pub struct AiController(pub Box<dyn PaddleController>);
+
+// Selectable AI personalities, picked per match from the settings screen or rules file
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AiPersonality {
+    #[default]
+    Tracker,
+    Predictor,
+    Aimer,
+    HumanLike,
+    Perfect,
+}
+
+impl AiPersonality {
+    pub const ALL: [AiPersonality; 5] = [
+        AiPersonality::Tracker,
+        AiPersonality::Predictor,
+        AiPersonality::Aimer,
+        AiPersonality::HumanLike,
+        AiPersonality::Perfect,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AiPersonality::Tracker => "Tracker",
+            AiPersonality::Predictor => "Predictor",
+            AiPersonality::Aimer => "Aimer",
+            AiPersonality::HumanLike => "Human-like",
+            AiPersonality::Perfect => "Perfect",
+        }
+    }
+
+    // Controllers that need randomness get their own generator seeded from the match's
+    pub fn controller(&self, rng: &mut GameRng) -> AiController {
+        AiController(match self {
+            AiPersonality::Tracker => Box::new(EasingTracker::default()),
+            AiPersonality::Predictor => Box::new(Predictor),
+            AiPersonality::Aimer => Box::new(Aimer),
+            AiPersonality::HumanLike => {
+                Box::new(HumanLike::new(StdRng::seed_from_u64(rng.rng.gen())))
+            }
+            AiPersonality::Perfect => Box::new(Perfect),
+        })
+    }
+}
+
+// ##############################################################
+// # Controllers
+// ##############################################################
+
+// Chases the ball's height with an easing function, the original AI
+// Tuned by GameRules::ai_skill, scaled by the adaptive difficulty if it's on
+pub struct EasingTracker {
+    skill_scale: f32,
+}
+
+impl Default for EasingTracker {
+    fn default() -> Self {
+        Self { skill_scale: 1. }
+    }
+}
+
+impl PaddleController for EasingTracker {
+    fn update(&mut self, view: &PaddleView, rules: &GameRules, _delta: f32) -> f32 {
+        let a_to_b = view.ball - view.paddle;
+
+        // If the AI paddle is above the ball, move down, if it's below move up
+        exp_easeout(
+            a_to_b.y / rules.field_height,
+            rules.ai_skill * self.skill_scale,
+        )
+    }
+
+    fn set_difficulty(&mut self, level: f32) {
+        self.skill_scale = f32_map(0., 1., 0.5, 1.5, level);
+    }
+}
+
+// Works out where the ball will cross the paddle, bounces and all, and waits there
+pub struct Predictor;
+
+impl PaddleController for Predictor {
+    fn update(&mut self, view: &PaddleView, rules: &GameRules, _delta: f32) -> f32 {
+        // Drift back to the middle while the ball is heading away
+        let target = predict_intercept(view, rules).unwrap_or(0.);
+        steer_towards(view.paddle.y, target, rules)
+    }
+}
+
+// Predicts the intercept like the Predictor, then picks which part of the paddle to
+// hit the ball with so the return lands as far from the opponent as possible
+pub struct Aimer;
+
+impl Aimer {
+    const SAMPLES: usize = 12; // Contact points tried along the paddle
+    const MARGIN: f32 = 0.8; // Fraction of the half paddle it aims with, so it doesn't miss on an edge
+}
+
+impl PaddleController for Aimer {
+    fn update(&mut self, view: &PaddleView, rules: &GameRules, _delta: f32) -> f32 {
+        let Some(intercept) = predict_arrival(view.ball, view.ball_velocity, view.paddle.x, rules)
+        else {
+            return steer_towards(view.paddle.y, 0., rules);
+        };
+
+        // The ball leaves the paddle heading back towards the opponent, with its vertical
+        // direction unchanged, so only the steepness is up to us
+        let direction = Vec2::new(
+            view.opponent.x - view.paddle.x,
+            intercept.vertical_direction,
+        );
+        let max_offset = rules.paddle_height / 2. * Self::MARGIN;
+
+        let mut best_offset = 0.;
+        let mut best_distance = f32::MIN;
+        for sample in 0..=Self::SAMPLES {
+            let offset = max_offset * sample as f32 / Self::SAMPLES as f32;
+            let outgoing = paddle_hit_velocity(offset, direction, rules);
+            let origin = Vec2::new(view.paddle.x, intercept.y);
+
+            if let Some(arrival) = predict_arrival(origin, outgoing, view.opponent.x, rules) {
+                let distance = (arrival.y - view.opponent.y).abs();
+                if distance > best_distance {
+                    best_distance = distance;
+                    best_offset = offset;
+                }
+            }
+        }
+
+        // Hitting above or below center gives the same return, so use whichever is closer
+        let max_y = rules.paddle_max_y();
+        let above = (intercept.y + best_offset).clamp(-max_y, max_y);
+        let below = (intercept.y - best_offset).clamp(-max_y, max_y);
+        let target = if (above - view.paddle.y).abs() < (below - view.paddle.y).abs() {
+            above
+        } else {
+            below
+        };
+
+        steer_towards(view.paddle.y, target, rules)
+    }
+}
+
+// Predicts like the Predictor, but only looks every so often, misjudges where the
+// ball is going, and doesn't always move at full speed
+pub struct HumanLike {
+    rng: StdRng,
+    target: f32,
+    next_look: f32,     // Seconds until the target is updated again
+    reaction_time: f32, // Average seconds between looks at the ball
+    aim_error: f32,     // Furthest the guessed intercept can be off by in world units
+    effort: f32,        // Fraction of full paddle speed it moves at
+}
+
+impl HumanLike {
+    pub fn new(rng: StdRng) -> Self {
+        let mut ai = Self {
+            rng,
+            target: 0.,
+            next_look: 0.,
+            reaction_time: 0.,
+            aim_error: 0.,
+            effort: 0.,
+        };
+        ai.set_difficulty(0.5);
+        ai
+    }
+}
+
+impl PaddleController for HumanLike {
+    fn update(&mut self, view: &PaddleView, rules: &GameRules, delta: f32) -> f32 {
+        self.next_look -= delta;
+
+        if self.next_look <= 0. {
+            self.next_look = self
+                .rng
+                .gen_range(self.reaction_time * 0.5..self.reaction_time * 1.5);
+
+            let noise = self.rng.gen_range(-self.aim_error..=self.aim_error);
+            self.target = predict_intercept(view, rules).map_or(0., |y| y + noise);
+        }
+
+        steer_towards(view.paddle.y, self.target, rules) * self.effort
+    }
+
+    fn set_difficulty(&mut self, level: f32) {
+        self.reaction_time = f32_map(0., 1., 0.45, 0.05, level);
+        self.aim_error = f32_map(0., 1., 80., 0., level);
+        self.effort = f32_map(0., 1., 0.6, 1., level);
+    }
+}
+
+// Never misjudges the ball: shadows it while it's heading away and goes straight for the
+// intercept when it's coming back. Held to the paddle speed limit like everyone else, so a
+// fast enough ball can still get past it
+pub struct Perfect;
+
+impl PaddleController for Perfect {
+    fn update(&mut self, view: &PaddleView, rules: &GameRules, _delta: f32) -> f32 {
+        // Intercepts next to a gutter are out of reach, so wait as close as the paddle can get
+        let max_y = rules.paddle_max_y();
+        let target = predict_intercept(view, rules)
+            .unwrap_or(view.ball.y)
+            .clamp(-max_y, max_y);
+        steer_towards(view.paddle.y, target, rules)
+    }
+}
+
+// ##############################################################
+// # Helpers
+// ##############################################################
+
+// Velocity that moves a paddle towards target_y as fast as it's allowed without overshooting
+pub fn steer_towards(paddle_y: f32, target_y: f32, rules: &GameRules) -> f32 {
+    ((target_y - paddle_y) / rules.paddle_speed).clamp(-1., 1.)
+}
+
+// Where and which way the ball is going when it reaches some x position
+pub struct Arrival {
+    pub y: f32,
+    pub vertical_direction: f32, // 1 if it's heading up, -1 if it's heading down
+}
+
+// Height at which the ball will reach the paddle's x position, accounting for
+// bounces off the gutters. None if the ball is moving away from the paddle
+pub fn predict_intercept(view: &PaddleView, rules: &GameRules) -> Option<f32> {
+    predict_arrival(view.ball, view.ball_velocity, view.paddle.x, rules).map(|arrival| arrival.y)
+}
+
+// Follow a ball from position at velocity until it reaches target_x, bouncing off the gutters
+// None if it's moving away from target_x
+pub fn predict_arrival(
+    position: Vec2,
+    velocity: Vec2,
+    target_x: f32,
+    rules: &GameRules,
+) -> Option<Arrival> {
+    let distance = target_x - position.x;
+    if velocity.x == 0. || distance.signum() != velocity.x.signum() {
+        return None;
+    }
+
+    let time = distance / velocity.x;
+    let unfolded_y = position.y + velocity.y * time;
+    let (y, flipped) = fold_into_field(unfolded_y, rules);
+
+    let vertical_direction = if flipped {
+        -velocity.y.signum()
+    } else {
+        velocity.y.signum()
+    };
+
+    Some(Arrival {
+        y,
+        vertical_direction,
+    })
+}
+
+// Reflect a height that ignores the gutters back into the space the ball can reach
+// Each bounce mirrors the path, so this repeats every two field heights. Also returns
+// whether the ball ends up travelling the opposite vertical direction
+pub fn fold_into_field(y: f32, rules: &GameRules) -> (f32, bool) {
+    // The ball collider's radius is its size, so it bounces that far from the gutter
+    let half_range = (rules.play_height() / 2. - rules.ball_size).max(0.);
+    if half_range == 0. {
+        return (0., false);
+    }
+
+    let period = half_range * 4.;
+    let wrapped = (y + half_range).rem_euclid(period);
+    if wrapped > half_range * 2. {
+        (period - wrapped - half_range, true)
+    } else {
+        (wrapped - half_range, false)
+    }
+}
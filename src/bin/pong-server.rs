@@ -0,0 +1,29 @@
+// Dedicated server. Runs matches headless for clients started with --server, pairing them
+// up by match code, and is the only one whose simulation counts
+use bevy_pong::{rules, server};
+
+fn main() {
+    let rules = rules::GameRules::load(rules::RULES_ASSET).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+
+    let args: Vec<String> = std::env::args().collect();
+    let port = match args.iter().position(|arg| arg == "--port") {
+        Some(index) => args
+            .get(index + 1)
+            .and_then(|port| port.parse().ok())
+            .unwrap_or_else(|| {
+                eprintln!("--port needs a valid value");
+                std::process::exit(1);
+            }),
+        None => server::DEFAULT_PORT,
+    };
+
+    let mut server = server::PongServer::bind(port, rules).unwrap_or_else(|err| {
+        eprintln!("Couldn't listen on port {port}: {err}");
+        std::process::exit(1);
+    });
+    println!("Listening on port {port}");
+    server.run();
+}
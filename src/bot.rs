@@ -0,0 +1,337 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    process::{Child, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use avian2d::prelude::LinearVelocity;
+use bevy::{
+    app::{App, FixedUpdate},
+    ecs::schedule::IntoSystemConfigs,
+    math::Vec2,
+    prelude::{
+        in_state, not, resource_exists, Commands, Entity, OnEnter, Plugin, Query, Res, ResMut,
+        Resource, With,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actors::apply_player_input, ai::AiController, replay::Playback, setup::spawn_paddles,
+    structure::*,
+};
+
+// Longest a bot gets to answer each tick before its paddle stands still for it
+pub const DEFAULT_BOT_TIMEOUT: Duration = Duration::from_millis(10);
+
+// Bots are other programs steering a paddle, written in whatever language. Every tick the game
+// sends each bot one line of JSON with a BotState, and the bot answers with one line holding a
+// BotAction, like {"move": 1.0}. No answer in time means no move that tick
+// A bot is either a command run with the state on its stdin and actions read from its stdout,
+// or tcp:<address> for one that's already listening on a socket
+pub struct PongBotPlugin;
+
+impl Plugin for PongBotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(AppState::InGame),
+            assign_bots
+                .after(spawn_paddles)
+                .run_if(resource_exists::<Bots>),
+        );
+        app.add_systems(
+            FixedUpdate,
+            bot_movement
+                .in_set(GameplaySet::Input)
+                .before(apply_player_input)
+                .run_if(resource_exists::<Bots>)
+                .run_if(not(resource_exists::<Playback>))
+                .run_if(not(in_state(MatchState::GameOver))),
+        );
+    }
+}
+
+// ##############################################################
+// # Protocol
+// ##############################################################
+
+// What a bot is told every tick, from its own side of the table
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BotState {
+    pub tick: u64,
+    pub side: Side,
+    pub ball: Vec2,
+    pub ball_velocity: Vec2,
+    pub paddle: Vec2,
+    pub opponent: Vec2,
+    pub score: u32,
+    pub opponent_score: u32,
+}
+
+// What a bot answers with. Movement is a paddle velocity between -1 and 1, like a held key
+// Answers can say which tick they're for, so a late answer isn't taken for the next tick's
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct BotAction {
+    #[serde(default)]
+    pub tick: Option<u64>,
+    #[serde(rename = "move", default)]
+    pub movement: f32,
+    #[serde(default)]
+    pub serve: bool,
+}
+
+// ##############################################################
+// # Connections
+// ##############################################################
+
+// The bots playing this game, at most one a side. Paddles with a bot are marked Bot instead of
+// Player or Ai when the match starts
+#[derive(Resource)]
+pub struct Bots {
+    pub timeout: Duration,
+    left: Option<BotLink>,
+    right: Option<BotLink>,
+}
+
+impl Bots {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            left: None,
+            right: None,
+        }
+    }
+
+    // Start or connect to the bot described by spec and give it a side's paddle
+    pub fn add(&mut self, side: Side, spec: &str) -> Result<(), io::Error> {
+        let link = match spec.strip_prefix("tcp:") {
+            Some(addr) => BotLink::connect(addr)?,
+            None => BotLink::spawn(spec)?,
+        };
+        match side {
+            Side::Left => self.left = Some(link),
+            Side::Right => self.right = Some(link),
+        }
+        Ok(())
+    }
+
+    pub fn has(&self, side: Side) -> bool {
+        match side {
+            Side::Left => self.left.is_some(),
+            Side::Right => self.right.is_some(),
+        }
+    }
+}
+
+// Lines to and from one bot. Reading and writing happen on their own threads so a bot that's
+// stuck or gone can't hold up the game
+struct BotLink {
+    name: String,
+    outgoing: Sender<String>,
+    replies: Mutex<Receiver<String>>,
+    child: Option<Child>,
+    closed: bool,
+}
+
+impl BotLink {
+    fn spawn(command: &str) -> Result<Self, io::Error> {
+        let mut words = command.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty bot command"))?;
+
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        let mut link = Self::new(command, stdin, stdout);
+        link.child = Some(child);
+        Ok(link)
+    }
+
+    fn connect(addr: &str) -> Result<Self, io::Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(addr, stream.try_clone()?, stream))
+    }
+
+    fn new(
+        name: &str,
+        mut writer: impl Write + Send + 'static,
+        reader: impl io::Read + Send + 'static,
+    ) -> Self {
+        let (outgoing, lines) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            for line in lines {
+                if writeln!(writer, "{line}")
+                    .and_then(|_| writer.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let (replies, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if replies.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            name: name.to_string(),
+            outgoing,
+            replies: Mutex::new(incoming),
+            child: None,
+            closed: false,
+        }
+    }
+
+    fn send(&mut self, state: &BotState) {
+        let line = serde_json::to_string(state).expect("bot states always serialize");
+        if self.outgoing.send(line).is_err() {
+            self.close("stopped reading");
+        }
+    }
+
+    // Wait until the deadline for this tick's action, skipping answers meant for earlier ticks
+    fn action(&mut self, tick: u64, deadline: Instant) -> Option<BotAction> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let line = self
+                .replies
+                .get_mut()
+                .expect("only the bot system reads replies")
+                .recv_timeout(timeout);
+
+            match line {
+                Ok(line) => match serde_json::from_str::<BotAction>(&line) {
+                    Ok(action) if action.tick.is_none_or(|sent| sent >= tick) => {
+                        return Some(action)
+                    }
+                    Ok(_) => (),
+                    Err(err) => eprintln!("Bot {} sent a bad action {line:?}: {err}", self.name),
+                },
+                Err(RecvTimeoutError::Timeout) => return None,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.close("stopped answering");
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, reason: &str) {
+        if !self.closed {
+            eprintln!("Bot {} {reason}, its paddle will stand still", self.name);
+            self.closed = true;
+        }
+    }
+}
+
+impl Drop for BotLink {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// ##############################################################
+// # Systems
+// ##############################################################
+
+// Hand the paddles of sides with a bot over to it
+fn assign_bots(
+    mut commands: Commands,
+    bots: Res<Bots>,
+    paddles: Query<(Entity, &Side), With<Paddle>>,
+) {
+    for (entity, side) in &paddles {
+        if bots.has(*side) {
+            commands
+                .entity(entity)
+                .remove::<(Player, Ai, AiController)>()
+                .insert(Bot);
+        }
+    }
+}
+
+// Tell every bot where things are, then give them all until the timeout to answer
+fn bot_movement(
+    mut bots: ResMut<Bots>,
+    mut tick_input: ResMut<TickInput>,
+    ball: Query<(&Position, &LinearVelocity), With<Ball>>,
+    paddles: Query<(&Position, &Side), With<Paddle>>,
+    score: Res<Score>,
+    tick: Res<Tick>,
+) {
+    let Ok((ball, ball_velocity)) = ball.get_single() else {
+        return;
+    };
+    let paddle = |side: Side| {
+        paddles
+            .iter()
+            .find(|(_, paddle_side)| **paddle_side == side)
+            .map_or(Vec2::ZERO, |(position, _)| position.0)
+    };
+    let score_for = |side: Side| match side {
+        Side::Left => score.ai,
+        Side::Right => score.player,
+    };
+
+    let bots = &mut *bots;
+    let deadline = Instant::now() + bots.timeout;
+    let mut links = [
+        (Side::Left, Side::Right, bots.left.as_mut()),
+        (Side::Right, Side::Left, bots.right.as_mut()),
+    ];
+
+    for (side, opponent, link) in &mut links {
+        if let Some(link) = link.as_mut().filter(|link| !link.closed) {
+            link.send(&BotState {
+                tick: tick.0,
+                side: *side,
+                ball: ball.0,
+                ball_velocity: ball_velocity.0,
+                paddle: paddle(*side),
+                opponent: paddle(*opponent),
+                score: score_for(*side),
+                opponent_score: score_for(*opponent),
+            });
+        }
+    }
+
+    for (side, _, link) in &mut links {
+        let Some(link) = link.as_mut() else {
+            continue;
+        };
+        let action = if link.closed {
+            None
+        } else {
+            link.action(tick.0, deadline)
+        }
+        .unwrap_or_default();
+
+        let input = tick_input.side_mut(*side);
+        input.movement = clamp_movement(action.movement);
+        input.serve = action.serve;
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::VecDeque,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    app::{App, Update},
+    prelude::{
+        in_state, resource_exists, Commands, EventWriter, IntoSystemConfigs, NextState, OnExit,
+        Plugin, Query, Res, ResMut, Resource, State, Transform, With, Without,
+    },
+    time::{Time, Virtual},
+};
+
+use crate::{
+    headless::TICK,
+    server::{ClientMessage, Connection, MatchSnapshot, ServerMessage},
+    structure::*,
+};
+
+// How far behind the newest snapshot the field is drawn, about six ticks
+const INTERPOLATION_DELAY: Duration = Duration::from_millis(94);
+
+pub struct PongClientPlugin;
+
+impl Plugin for PongClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                receive_messages,
+                (send_input, interpolate_snapshots).run_if(in_state(AppState::InGame)),
+            )
+                .chain()
+                .run_if(resource_exists::<ServerConnection>),
+        );
+        app.add_systems(OnExit(AppState::InGame), disconnect);
+    }
+}
+
+// Connection to a pong-server running the match. While it's around nothing is simulated
+// locally, the field is drawn from the server's snapshots and our input is sent to it
+#[derive(Resource)]
+pub struct ServerConnection {
+    pub side: Option<Side>, // Paddle we control, once the match has started
+    connection: Connection,
+    snapshots: SnapshotBuffer,
+    sent: SideInput,
+}
+
+impl ServerConnection {
+    // Open a new match on the server, its code is printed once the server replies
+    pub fn host(addr: SocketAddr) -> Result<Self, io::Error> {
+        println!("Hosting a match on {addr}...");
+        Self::new(addr, ClientMessage::Host)
+    }
+
+    // Join the match someone else opened
+    pub fn join(addr: SocketAddr, code: &str) -> Result<Self, io::Error> {
+        println!("Joining match {code} on {addr}...");
+        Self::new(addr, ClientMessage::Join(code.to_string()))
+    }
+
+    fn new(addr: SocketAddr, request: ClientMessage) -> Result<Self, io::Error> {
+        let mut connection = Connection::connect(addr)?;
+        connection.send(&request);
+
+        Ok(Self {
+            side: None,
+            connection,
+            snapshots: SnapshotBuffer::new(INTERPOLATION_DELAY),
+            sent: SideInput::default(),
+        })
+    }
+}
+
+// Snapshots of a match simulated somewhere else, drawn a fixed delay behind the newest one so
+// there's usually a snapshot either side to blend between
+pub struct SnapshotBuffer {
+    delay: f64, // In ticks
+    snapshots: VecDeque<MatchSnapshot>,
+    newest_arrived: Instant,
+}
+
+impl SnapshotBuffer {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay: delay.as_secs_f64() / TICK.as_secs_f64(),
+            snapshots: VecDeque::new(),
+            newest_arrived: Instant::now(),
+        }
+    }
+
+    // A snapshot for a tick that's already been seen replaces it and everything after it,
+    // which happens when a rollback resimulated those ticks or a new match started
+    pub fn push(&mut self, snapshot: MatchSnapshot) {
+        while self
+            .snapshots
+            .back()
+            .is_some_and(|newest| newest.tick >= snapshot.tick)
+        {
+            self.snapshots.pop_back();
+        }
+        self.snapshots.push_back(snapshot);
+        self.newest_arrived = Instant::now();
+
+        // Only one snapshot from before the tick being drawn is needed, to blend from
+        if let Some(render_tick) = self.render_tick() {
+            while self
+                .snapshots
+                .get(1)
+                .is_some_and(|next| next.tick as f64 <= render_tick)
+            {
+                self.snapshots.pop_front();
+            }
+        }
+    }
+
+    // Tick to draw, where we think the sender is now minus the delay
+    pub fn render_tick(&self) -> Option<f64> {
+        let newest = self.snapshots.back()?;
+        let ticks_since = self.newest_arrived.elapsed().as_secs_f64() / TICK.as_secs_f64();
+        Some((newest.tick as f64 + ticks_since - self.delay).min(newest.tick as f64))
+    }
+
+    // Where everything should be drawn this frame, blended from the two snapshots around the
+    // render tick
+    pub fn interpolated(&self) -> Option<MatchSnapshot> {
+        let render_tick = self.render_tick()?;
+
+        let Some((from, to)) = self
+            .snapshots
+            .iter()
+            .zip(self.snapshots.iter().skip(1))
+            .find(|(_, to)| to.tick as f64 > render_tick)
+        else {
+            return self.snapshots.back().cloned();
+        };
+
+        // Not enough history yet to go back that far
+        if render_tick <= from.tick as f64 {
+            return Some(from.clone());
+        }
+
+        // The ball jumps back to the middle after a point, don't draw it sliding there
+        if from.score != to.score {
+            return Some(to.clone());
+        }
+
+        let t = ((render_tick - from.tick as f64) / (to.tick - from.tick) as f64) as f32;
+        Some(MatchSnapshot {
+            tick: from.tick,
+            ball: from.ball.lerp(to.ball, t),
+            ball_velocity: from.ball_velocity.lerp(to.ball_velocity, t),
+            left: from.left.lerp(to.left, t),
+            right: from.right.lerp(to.right, t),
+            score: from.score.clone(),
+            rally: from.rally,
+        })
+    }
+}
+
+// Move the ball and paddles to where a snapshot has them
+#[allow(clippy::type_complexity)]
+pub fn draw_snapshot(
+    snapshot: &MatchSnapshot,
+    balls: &mut Query<&mut Transform, With<Ball>>,
+    paddles: &mut Query<(&mut Transform, &Side), (With<Paddle>, Without<Ball>)>,
+) {
+    for mut transform in balls.iter_mut() {
+        transform.translation = snapshot.ball.extend(transform.translation.z);
+    }
+
+    for (mut transform, side) in paddles.iter_mut() {
+        let position = match side {
+            Side::Left => snapshot.left,
+            Side::Right => snapshot.right,
+        };
+        transform.translation = position.extend(transform.translation.z);
+    }
+}
+
+// ##############################################################
+// # Systems
+// ##############################################################
+
+fn receive_messages(
+    mut commands: Commands,
+    mut server: ResMut<ServerConnection>,
+    app_state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut time: ResMut<Time<Virtual>>,
+    mut score: ResMut<Score>,
+    mut match_won: EventWriter<MatchWon>,
+) {
+    for message in server.connection.receive::<ServerMessage>() {
+        match message {
+            ServerMessage::Hosted(code) => {
+                println!("Match code {code}, waiting for an opponent...");
+            }
+            ServerMessage::Open(codes) => println!("Open matches: {}", codes.join(", ")),
+            ServerMessage::Refused(reason) => {
+                eprintln!("{reason}");
+                commands.remove_resource::<ServerConnection>();
+            }
+            ServerMessage::Started { side, rules } => {
+                println!("Match started, you're the {side:?} paddle");
+                server.side = Some(side);
+                commands.insert_resource(rules);
+                commands.insert_resource(GameMode::Online);
+                // The server runs the match, here it's only drawn
+                time.set_relative_speed(0.);
+                next_state.set(AppState::InGame);
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                if *score != snapshot.score {
+                    *score = snapshot.score.clone();
+                }
+                server.snapshots.push(snapshot);
+            }
+            ServerMessage::MatchWon(winner) => {
+                match_won.send(MatchWon(winner));
+            }
+            ServerMessage::Closed(reason) => {
+                println!("{reason}");
+                next_state.set(AppState::MainMenu);
+            }
+        }
+    }
+
+    if server.connection.closed {
+        eprintln!("Lost connection to the server");
+        commands.remove_resource::<ServerConnection>();
+        if *app_state.get() == AppState::InGame {
+            next_state.set(AppState::MainMenu);
+        }
+    }
+}
+
+// Either side's keys work, since there's only one local player
+fn send_input(mut server: ResMut<ServerConnection>, mut tick_input: ResMut<TickInput>) {
+    let movement = if tick_input.right.movement != 0. {
+        tick_input.right.movement
+    } else {
+        tick_input.left.movement
+    };
+    let input = SideInput {
+        movement,
+        serve: tick_input.left.serve || tick_input.right.serve,
+    };
+
+    // No ticks run here to clear the serve presses, so do it once they're sent
+    tick_input.left.serve = false;
+    tick_input.right.serve = false;
+
+    if input != server.sent {
+        server.connection.send(&ClientMessage::Input {
+            movement: input.movement,
+            serve: input.serve,
+        });
+        server.sent = input;
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn interpolate_snapshots(
+    server: Res<ServerConnection>,
+    mut rally: ResMut<Rally>,
+    mut balls: Query<&mut Transform, With<Ball>>,
+    mut paddles: Query<(&mut Transform, &Side), (With<Paddle>, Without<Ball>)>,
+) {
+    if let Some(snapshot) = server.snapshots.interpolated() {
+        if *rally != snapshot.rally {
+            *rally = snapshot.rally;
+        }
+        draw_snapshot(&snapshot, &mut balls, &mut paddles);
+    }
+}
+
+fn disconnect(mut commands: Commands, mut time: ResMut<Time<Virtual>>) {
+    commands.remove_resource::<ServerConnection>();
+    time.set_relative_speed(1.);
+}
@@ -0,0 +1,309 @@
+use std::{fs, io};
+
+use bevy::{
+    app::{App, PreUpdate},
+    ecs::system::SystemParam,
+    input::{
+        gamepad::{
+            Gamepad, GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType,
+            GamepadConnection, GamepadConnectionEvent,
+        },
+        Axis, ButtonInput, InputSystem,
+    },
+    prelude::{EventReader, IntoSystemConfigs, KeyCode, OnExit, Plugin, Res, ResMut, Resource},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::structure::{exp_easeout, Action, AppState, Side};
+
+// Where key bindings are saved, relative to the working directory
+pub const CONTROLS_PATH: &str = "controls.ron";
+
+pub struct PongControlsPlugin;
+
+impl Plugin for PongControlsPlugin {
+    fn build(&self, app: &mut App) {
+        // The defaults, the windowed game replaces them with the saved controls. Headless
+        // matches, servers and tests shouldn't depend on whatever's in the working directory
+        app.init_resource::<InputMap>();
+        app.init_resource::<GamepadAssignments>();
+        app.add_systems(PreUpdate, assign_gamepads.after(InputSystem));
+        app.add_systems(OnExit(AppState::Controls), save_controls);
+    }
+}
+
+// ##############################################################
+// # Key Bindings
+// ##############################################################
+
+// Keys bound to each logical action for a single player
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Bindings {
+    pub move_up: KeyCode,
+    pub move_down: KeyCode,
+    pub serve: KeyCode,
+    pub pause: KeyCode,
+}
+
+impl Bindings {
+    pub fn get(&self, action: Action) -> KeyCode {
+        match action {
+            Action::MoveUp => self.move_up,
+            Action::MoveDown => self.move_down,
+            Action::Serve => self.serve,
+            Action::Pause => self.pause,
+        }
+    }
+
+    pub fn set(&mut self, action: Action, key: KeyCode) {
+        match action {
+            Action::MoveUp => self.move_up = key,
+            Action::MoveDown => self.move_down = key,
+            Action::Serve => self.serve = key,
+            Action::Pause => self.pause = key,
+        }
+    }
+}
+
+// How analog stick deflection is turned into paddle movement
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StickSettings {
+    pub deadzone: f32, // Deflection below this is ignored, between 0 and 1
+    pub curve: f32, // Easing magnitude, 1 is linear and higher is more responsive near the center
+}
+
+impl StickSettings {
+    // Map raw stick deflection to a paddle velocity between -1 and 1
+    pub fn response(&self, deflection: f32) -> f32 {
+        let magnitude = deflection.abs().min(1.);
+        if magnitude <= self.deadzone {
+            return 0.;
+        }
+
+        // Rescale so movement starts from zero at the edge of the deadzone
+        let t = (magnitude - self.deadzone) / (1. - self.deadzone);
+        exp_easeout(t, self.curve) * deflection.signum()
+    }
+
+    // Reject settings response can't work with, like a deadzone covering the whole stick
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0. ..1.).contains(&self.deadzone) {
+            return Err(format!(
+                "stick deadzone must be at least 0 and under 1, got {}",
+                self.deadzone
+            ));
+        }
+        if !self.curve.is_finite() || self.curve <= 0. {
+            return Err(format!(
+                "stick curve must be a positive number, got {}",
+                self.curve
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Key bindings for both players plus gamepad tuning, persisted to CONTROLS_PATH
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct InputMap {
+    pub left: Bindings,
+    pub right: Bindings,
+    pub stick: StickSettings,
+    pub pointer_control: bool, // Whether player one's paddle follows the mouse or touch point
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        Self {
+            left: Bindings {
+                move_up: KeyCode::KeyW,
+                move_down: KeyCode::KeyS,
+                serve: KeyCode::Space,
+                pause: KeyCode::Escape,
+            },
+            right: Bindings {
+                move_up: KeyCode::ArrowUp,
+                move_down: KeyCode::ArrowDown,
+                serve: KeyCode::Enter,
+                pause: KeyCode::Escape,
+            },
+            stick: StickSettings {
+                deadzone: 0.15,
+                curve: 1.5,
+            },
+            // Most web players won't have arrow keys
+            pointer_control: cfg!(target_arch = "wasm32"),
+        }
+    }
+}
+
+impl InputMap {
+    pub fn side(&self, side: Side) -> &Bindings {
+        match side {
+            Side::Left => &self.left,
+            Side::Right => &self.right,
+        }
+    }
+
+    pub fn side_mut(&mut self, side: Side) -> &mut Bindings {
+        match side {
+            Side::Left => &mut self.left,
+            Side::Right => &mut self.right,
+        }
+    }
+
+    // Read saved bindings, using the defaults if there are none or they can't be read
+    // Bad stick settings only reset the stick, the key bindings are still used
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(source) => match ron::from_str::<Self>(&source) {
+                Ok(mut input_map) => {
+                    if let Err(err) = input_map.stick.validate() {
+                        eprintln!("Invalid {path}, using the default stick settings: {err}");
+                        input_map.stick = Self::default().stick;
+                    }
+                    input_map
+                }
+                Err(err) => {
+                    eprintln!("Couldn't parse {path}, using default controls: {err}");
+                    Self::default()
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(err) => {
+                eprintln!("Couldn't read {path}, using default controls: {err}");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), io::Error> {
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, source)
+    }
+}
+
+// ##############################################################
+// # Gamepads
+// ##############################################################
+
+// Which gamepad, if any, drives each side's paddle
+#[derive(Resource, Default, Debug)]
+pub struct GamepadAssignments {
+    pub left: Option<Gamepad>,
+    pub right: Option<Gamepad>,
+}
+
+impl GamepadAssignments {
+    pub fn side(&self, side: Side) -> Option<Gamepad> {
+        match side {
+            Side::Left => self.left,
+            Side::Right => self.right,
+        }
+    }
+}
+
+// Give newly connected pads the first free slot, right (player one) first, and
+// free the slot back up when they're unplugged
+fn assign_gamepads(
+    mut events: EventReader<GamepadConnectionEvent>,
+    mut assignments: ResMut<GamepadAssignments>,
+) {
+    for event in events.read() {
+        match event.connection {
+            GamepadConnection::Connected(_) => {
+                if assignments.right.is_none() {
+                    assignments.right = Some(event.gamepad);
+                } else if assignments.left.is_none() {
+                    assignments.left = Some(event.gamepad);
+                }
+            }
+            GamepadConnection::Disconnected => {
+                if assignments.right == Some(event.gamepad) {
+                    assignments.right = None;
+                }
+                if assignments.left == Some(event.gamepad) {
+                    assignments.left = None;
+                }
+            }
+        }
+    }
+}
+
+// Gamepad buttons for each action, these aren't rebindable
+fn gamepad_button(action: Action) -> GamepadButtonType {
+    match action {
+        Action::MoveUp => GamepadButtonType::DPadUp,
+        Action::MoveDown => GamepadButtonType::DPadDown,
+        Action::Serve => GamepadButtonType::South,
+        Action::Pause => GamepadButtonType::Start,
+    }
+}
+
+// ##############################################################
+// # Player Input
+// ##############################################################
+
+// Reads logical actions for either side from the keyboard, falling back to that side's gamepad
+#[derive(SystemParam)]
+pub struct PlayerInput<'w> {
+    keyboard: Res<'w, ButtonInput<KeyCode>>,
+    input_map: Res<'w, InputMap>,
+    assignments: Res<'w, GamepadAssignments>,
+    pad_buttons: Res<'w, ButtonInput<GamepadButton>>,
+    pad_axes: Res<'w, Axis<GamepadAxis>>,
+}
+
+impl PlayerInput<'_> {
+    // Vertical paddle velocity between -1 and 1. Keys are digital, the stick is proportional
+    pub fn movement(&self, side: Side) -> f32 {
+        let bindings = self.input_map.side(side);
+        if self.keyboard.pressed(bindings.move_up) || self.pad_pressed(side, Action::MoveUp) {
+            return 1.;
+        } else if self.keyboard.pressed(bindings.move_down)
+            || self.pad_pressed(side, Action::MoveDown)
+        {
+            return -1.;
+        }
+
+        self.assignments
+            .side(side)
+            .and_then(|pad| {
+                self.pad_axes
+                    .get(GamepadAxis::new(pad, GamepadAxisType::LeftStickY))
+            })
+            .map_or(0., |deflection| self.input_map.stick.response(deflection))
+    }
+
+    pub fn just_pressed(&self, side: Side, action: Action) -> bool {
+        let pad_pressed = self.assignments.side(side).is_some_and(|pad| {
+            self.pad_buttons
+                .just_pressed(GamepadButton::new(pad, gamepad_button(action)))
+        });
+
+        pad_pressed
+            || self
+                .keyboard
+                .just_pressed(self.input_map.side(side).get(action))
+    }
+
+    pub fn any_just_pressed(&self, action: Action) -> bool {
+        self.just_pressed(Side::Left, action) || self.just_pressed(Side::Right, action)
+    }
+
+    fn pad_pressed(&self, side: Side, action: Action) -> bool {
+        self.assignments.side(side).is_some_and(|pad| {
+            self.pad_buttons
+                .pressed(GamepadButton::new(pad, gamepad_button(action)))
+        })
+    }
+}
+
+// Write bindings to disk when leaving the rebind screen
+fn save_controls(input_map: Res<InputMap>) {
+    if let Err(err) = input_map.save(CONTROLS_PATH) {
+        eprintln!("Couldn't save controls to {CONTROLS_PATH}: {err}");
+    }
+}
@@ -0,0 +1,115 @@
+use bevy::{
+    app::{App, FixedUpdate},
+    ecs::schedule::IntoSystemConfigs,
+    prelude::{
+        in_state, resource_changed, EventReader, OnEnter, Plugin, Query, Res, ResMut, Resource,
+    },
+};
+
+use crate::{ai::AiController, rules::GameRules, structure::*};
+
+pub struct PongDifficultyPlugin;
+
+impl Plugin for PongDifficultyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AdaptiveDifficulty>();
+        app.add_systems(OnEnter(AppState::InGame), reset_difficulty);
+        app.add_systems(
+            FixedUpdate,
+            (
+                adjust_difficulty.run_if(|rules: Res<GameRules>| rules.adaptive_difficulty),
+                apply_difficulty.run_if(resource_changed::<AdaptiveDifficulty>),
+            )
+                .chain()
+                .in_set(GameplaySet::Difficulty)
+                .run_if(in_state(AppState::InGame)),
+        );
+    }
+}
+
+// How much a single point moves the difficulty
+const STEP: f32 = 0.1;
+
+// One entry per point scored, so it can be inspected how the difficulty evolved over a match
+#[derive(Clone, Debug)]
+pub struct DifficultySample {
+    pub scorer: Scorer,
+    pub rally_length: u32,
+    pub level: f32, // Difficulty after adjusting for the point
+}
+
+// Rubber-banding state for the AI, between 0 (easiest) and 1 (hardest)
+// Only the Tracker and Human-like AIs have a difficulty to adjust, the Predictor, Aimer and
+// Perfect AIs play the same however the match is going
+#[derive(Resource, Clone, Debug)]
+pub struct AdaptiveDifficulty {
+    pub level: f32,
+    pub history: Vec<DifficultySample>,
+}
+
+impl Default for AdaptiveDifficulty {
+    fn default() -> Self {
+        Self {
+            level: 0.5,
+            history: Vec::new(),
+        }
+    }
+}
+
+impl AdaptiveDifficulty {
+    // Nudge the difficulty after a point that ended a rally_length hit rally. Points won by the
+    // AI push it down and points lost push it up, weighted so it settles where the AI wins
+    // target_ai_win_rate of them
+    pub fn record_point(&mut self, scorer: Scorer, rally_length: u32, rules: &GameRules) {
+        let target = rules.target_ai_win_rate;
+        let direction = match scorer {
+            Scorer::Ai => -(1. - target),
+            Scorer::Player => target,
+        };
+
+        // Short rallies mean one side is being outclassed, so react harder to them
+        let rally_weight = 1. + 1. / (1. + rally_length as f32);
+
+        self.level = (self.level + direction * STEP * rally_weight)
+            .clamp(rules.min_difficulty, rules.max_difficulty);
+
+        self.history.push(DifficultySample {
+            scorer,
+            rally_length,
+            level: self.level,
+        });
+    }
+}
+
+// Start every match in the middle of the allowed range
+fn reset_difficulty(mut difficulty: ResMut<AdaptiveDifficulty>, rules: Res<GameRules>) {
+    *difficulty = AdaptiveDifficulty {
+        level: (rules.min_difficulty + rules.max_difficulty) / 2.,
+        ..Default::default()
+    };
+}
+
+// The rally that just ended is still counted until the Phase set clears it
+fn adjust_difficulty(
+    mut difficulty: ResMut<AdaptiveDifficulty>,
+    mut events: EventReader<ScoreEvent>,
+    rally: Res<Rally>,
+    rules: Res<GameRules>,
+) {
+    for ScoreEvent(scorer) in events.read() {
+        difficulty.record_point(*scorer, rally.hits, &rules);
+    }
+}
+
+// Hand the current difficulty to every AI, including ones that were just spawned
+fn apply_difficulty(
+    mut controllers: Query<&mut AiController>,
+    difficulty: Res<AdaptiveDifficulty>,
+    rules: Res<GameRules>,
+) {
+    if rules.adaptive_difficulty {
+        for mut controller in &mut controllers {
+            controller.0.set_difficulty(difficulty.level);
+        }
+    }
+}
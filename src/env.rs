@@ -0,0 +1,191 @@
+use bevy::{
+    ecs::{
+        event::{Events, ManualEventReader},
+        query::Has,
+    },
+    math::{Vec2, Vec3Swizzles},
+    prelude::{App, Transform},
+};
+
+use crate::{headless::headless_app, rules::GameRules, server::MatchSnapshot, structure::*};
+
+// Longest an episode can go before it's cut off, about ten minutes of play
+pub const DEFAULT_MAX_TICKS: u32 = 64 * 60 * 10;
+
+// What an agent sees after every step, in world units with the origin at the center of the field
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Observation {
+    pub ball: Vec2,
+    pub ball_velocity: Vec2,
+    pub left_paddle: Vec2,
+    pub right_paddle: Vec2,
+}
+
+impl Observation {
+    pub const SIZE: usize = 8;
+
+    // Flattened in field order, for feeding straight into a model
+    pub fn to_array(&self) -> [f32; Self::SIZE] {
+        [
+            self.ball.x,
+            self.ball.y,
+            self.ball_velocity.x,
+            self.ball_velocity.y,
+            self.left_paddle.x,
+            self.left_paddle.y,
+            self.right_paddle.x,
+            self.right_paddle.y,
+        ]
+    }
+}
+
+impl From<MatchSnapshot> for Observation {
+    fn from(snapshot: MatchSnapshot) -> Self {
+        Self {
+            ball: snapshot.ball,
+            ball_velocity: snapshot.ball_velocity,
+            left_paddle: snapshot.left,
+            right_paddle: snapshot.right,
+        }
+    }
+}
+
+// Gym-style wrapper around a headless match, one fixed tick per step
+// The mode decides who's in control: in VsAi the agent plays the right paddle against the AI
+// on the left, in LocalVersus agents play both. Actions for AI paddles are ignored, they're
+// moved by ai_movement as usual
+// Rewards are from the right paddle's side, +1 for each point it wins and -1 for each it loses,
+// so an agent on the left should negate them
+pub struct PongEnv {
+    pub rules: GameRules,
+    pub mode: GameMode,
+    pub max_ticks: u32,
+    app: App,
+    ticks: u32,
+    score_events: ManualEventReader<ScoreEvent>,
+    match_won: ManualEventReader<MatchWon>,
+}
+
+impl PongEnv {
+    pub fn new(rules: GameRules, mode: GameMode) -> Self {
+        Self {
+            app: Self::build_app(rules.clone(), mode, 0),
+            rules,
+            mode,
+            max_ticks: DEFAULT_MAX_TICKS,
+            ticks: 0,
+            score_events: ManualEventReader::default(),
+            match_won: ManualEventReader::default(),
+        }
+    }
+
+    // Start a new match. The same seed and actions always play out the same episode
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.app = Self::build_app(self.rules.clone(), self.mode, seed);
+        self.ticks = 0;
+        self.score_events = ManualEventReader::default();
+        self.match_won = ManualEventReader::default();
+
+        // Enter the match and spawn everything
+        self.app.update();
+        self.observe()
+    }
+
+    // Move the paddles for one tick, actions are paddle velocities between -1 and 1
+    pub fn step(&mut self, action_left: f32, action_right: f32) -> (Observation, f32, bool) {
+        let mut tick_input = self.app.world_mut().resource_mut::<TickInput>();
+        tick_input.left.movement = clamp_movement(action_left);
+        tick_input.right.movement = clamp_movement(action_right);
+
+        self.app.update();
+        self.ticks += 1;
+
+        let world = self.app.world();
+        let reward: f32 = self
+            .score_events
+            .read(world.resource::<Events<ScoreEvent>>())
+            .map(|ScoreEvent(scorer)| match scorer {
+                Scorer::Player => 1.,
+                Scorer::Ai => -1.,
+            })
+            .sum();
+        let won = self
+            .match_won
+            .read(world.resource::<Events<MatchWon>>())
+            .count()
+            > 0;
+
+        let done = won || self.ticks >= self.max_ticks;
+        (self.observe(), reward, done)
+    }
+
+    pub fn score(&self) -> &Score {
+        self.app.world().resource::<Score>()
+    }
+
+    // Draw the field into an RGB image without a GPU, rows from the top, white shapes on black
+    // like the game. The field is stretched to fit whatever size is asked for
+    pub fn render(&mut self, width: usize, height: usize) -> Vec<u8> {
+        let mut pixels = vec![0; width * height * 3];
+        let field = Vec2::new(self.rules.field_width, self.rules.field_height);
+        let scale = Vec2::new(width as f32, height as f32) / field;
+
+        // Pixels count down from the top left corner, the world counts up from the middle
+        let to_pixels =
+            |point: Vec2| Vec2::new(point.x + field.x / 2., field.y / 2. - point.y) * scale;
+
+        let world = self.app.world_mut();
+        let mut shapes = world.query::<(&Transform, &Shape, Has<Ball>)>();
+        for (transform, shape, is_ball) in shapes.iter(world) {
+            let center = transform.translation.xy();
+            let half_size = Vec2::new(shape.0.x, -shape.0.y) / 2.;
+            let min = to_pixels(center - half_size);
+            let max = to_pixels(center + half_size);
+            fill(&mut pixels, width, height, min, max, is_ball);
+        }
+
+        pixels
+    }
+
+    // The app behind the environment, for anything the observation doesn't cover
+    pub fn app(&self) -> &App {
+        &self.app
+    }
+
+    fn build_app(rules: GameRules, mode: GameMode, seed: u64) -> App {
+        let mut app = headless_app(rules, mode, seed);
+        app.insert_resource(ExternalInput);
+        app
+    }
+
+    fn observe(&mut self) -> Observation {
+        MatchSnapshot::take(self.app.world_mut())
+            .map(Observation::from)
+            .unwrap_or_default()
+    }
+}
+
+// Paint the pixels whose centers are inside a box, or the ellipse in it if it's round
+fn fill(pixels: &mut [u8], width: usize, height: usize, min: Vec2, max: Vec2, round: bool) {
+    let center = (min + max) / 2.;
+    let radius = (max - min) / 2.;
+
+    let columns = (min.x.max(0.) as usize)..(max.x.ceil().max(0.) as usize).min(width);
+    let rows = (min.y.max(0.) as usize)..(max.y.ceil().max(0.) as usize).min(height);
+
+    for y in rows {
+        for x in columns.clone() {
+            let pixel = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let inside = if round {
+                ((pixel - center) / radius).length_squared() <= 1.
+            } else {
+                pixel.cmpge(min).all() && pixel.cmple(max).all()
+            };
+
+            if inside {
+                let index = (y * width + x) * 3;
+                pixels[index..index + 3].fill(255);
+            }
+        }
+    }
+}
@@ -1,16 +1,75 @@
+use std::time::Duration;
+
+use avian2d::prelude::{Physics, PhysicsTime, TimestepMode};
 use bevy::{
-    app::{App, FixedUpdate},
+    app::{App, FixedMain, FixedUpdate, Update},
+    ecs::schedule::{Condition, IntoSystemConfigs, IntoSystemSetConfigs},
     math::Vec3Swizzles,
-    prelude::{Plugin, Query, Transform},
+    prelude::{
+        in_state, AppExtStates, NextState, OnEnter, OnExit, Or, Plugin, Query, Res, ResMut, State,
+        Transform, With, World,
+    },
+    state::state::StateTransition,
+    time::{Fixed, Time, Virtual},
 };
 
-use crate::structure::*;
+use crate::{controls::PlayerInput, rules::GameRules, structure::*};
 
 pub struct PongGameStatePlugin;
 
 impl Plugin for PongGameStatePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(FixedUpdate, project_positions);
+        app.init_state::<AppState>();
+        app.add_sub_state::<MatchState>();
+        app.add_sub_state::<PauseState>();
+        app.enable_state_scoped_entities::<AppState>();
+        app.enable_state_scoped_entities::<PauseState>();
+        app.init_resource::<PhaseTimer>();
+        app.init_resource::<GameRng>();
+        app.init_resource::<Tick>();
+        // Physics runs in FixedPostUpdate, stepped by exactly one tick each time so a tick plays
+        // out the same however ticks fall across frames, and when run by hand with run_fixed_tick
+        app.insert_resource(Time::<Physics>::from_timestep(TimestepMode::FixedOnce {
+            delta: Time::<Fixed>::default().timestep(),
+        }));
+        app.configure_sets(
+            FixedUpdate,
+            (
+                GameplaySet::Sync,
+                GameplaySet::Input,
+                GameplaySet::Movement,
+                GameplaySet::Ball,
+                GameplaySet::Scoring,
+                GameplaySet::Difficulty,
+                GameplaySet::Phase,
+            )
+                .chain(),
+        );
+        app.add_systems(FixedUpdate, project_positions.in_set(GameplaySet::Sync));
+        app.add_systems(OnEnter(MatchState::Serving), reset_phase_timer);
+        app.add_systems(OnEnter(MatchState::PointScored), reset_phase_timer);
+        app.add_systems(OnEnter(PauseState::Paused), pause_time);
+        app.add_systems(OnExit(PauseState::Paused), resume_time);
+        app.add_systems(OnEnter(AppState::MainMenu), resume_time);
+        app.add_systems(
+            FixedUpdate,
+            (
+                advance_phase.run_if(
+                    in_state(MatchState::Serving).or_else(in_state(MatchState::PointScored)),
+                ),
+                serve_early.run_if(in_state(MatchState::Serving)),
+            )
+                .chain()
+                .in_set(GameplaySet::Phase),
+        );
+        app.add_systems(
+            FixedUpdate,
+            (advance_tick, apply_state_transitions)
+                .chain()
+                .after(GameplaySet::Phase),
+        );
+        app.add_systems(OnEnter(AppState::InGame), reset_tick);
+        app.add_systems(Update, toggle_pause.run_if(in_state(AppState::InGame)));
     }
 }
 
@@ -20,3 +79,90 @@ fn project_positions(mut positionables: Query<(&Transform, &mut Position)>) {
         position.0 = transform.translation.xy();
     }
 }
+
+// Restart the countdown whenever we enter a timed phase
+fn reset_phase_timer(mut timer: ResMut<PhaseTimer>, rules: Res<GameRules>) {
+    timer
+        .0
+        .set_duration(Duration::from_secs_f32(rules.serve_delay));
+    timer.0.reset();
+}
+
+// Once the phase timer runs out move on to the next phase of the match
+// PointScored -> Serving -> Playing
+fn advance_phase(
+    time: Res<Time>,
+    mut timer: ResMut<PhaseTimer>,
+    state: Res<State<MatchState>>,
+    mut next_state: ResMut<NextState<MatchState>>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        match state.get() {
+            MatchState::PointScored => next_state.set(MatchState::Serving),
+            MatchState::Serving => next_state.set(MatchState::Playing),
+            _ => (),
+        }
+    }
+}
+
+// Let a human player or bot serve without waiting out the serve delay
+#[allow(clippy::type_complexity)]
+fn serve_early(
+    tick_input: Res<TickInput>,
+    players: Query<&Side, Or<(With<Player>, With<Bot>)>>,
+    mut next_state: ResMut<NextState<MatchState>>,
+) {
+    for side in &players {
+        if tick_input.side(*side).serve {
+            next_state.set(MatchState::Playing);
+        }
+    }
+}
+
+// Open or close the pause menu with either player's pause key
+fn toggle_pause(
+    input: PlayerInput,
+    state: Res<State<PauseState>>,
+    mut next_state: ResMut<NextState<PauseState>>,
+) {
+    if input.any_just_pressed(Action::Pause) {
+        match state.get() {
+            PauseState::Running => next_state.set(PauseState::Paused),
+            PauseState::Paused => next_state.set(PauseState::Running),
+        }
+    }
+}
+
+fn advance_tick(mut tick: ResMut<Tick>) {
+    tick.0 += 1;
+}
+
+// Every match counts its ticks from zero
+fn reset_tick(mut tick: ResMut<Tick>) {
+    tick.0 = 0;
+}
+
+// State changes normally wait for the end of the frame, which could be several ticks away
+// Applying them every tick keeps a match the same no matter how ticks fall across frames
+fn apply_state_transitions(world: &mut World) {
+    world.run_schedule(StateTransition);
+}
+
+// Run a single FixedUpdate tick right away, the same way the fixed main loop would
+// Used to fast forward the simulation, e.g. when seeking through a replay
+pub fn run_fixed_tick(world: &mut World) {
+    let timestep = world.resource::<Time<Fixed>>().timestep();
+    world.resource_mut::<Time<Fixed>>().advance_by(timestep);
+    *world.resource_mut::<Time>() = world.resource::<Time<Fixed>>().as_generic();
+    world.run_schedule(FixedMain);
+    *world.resource_mut::<Time>() = world.resource::<Time<Virtual>>().as_generic();
+}
+
+// Stopping virtual time freezes FixedUpdate, and with it the physics and ball
+fn pause_time(mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+}
+
+fn resume_time(mut time: ResMut<Time<Virtual>>) {
+    time.unpause();
+}
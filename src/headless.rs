@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use avian2d::prelude::PhysicsPlugins;
+use bevy::{
+    ecs::event::{Events, ManualEventReader},
+    hierarchy::HierarchyPlugin,
+    input::InputPlugin,
+    prelude::{App, FixedPostUpdate, MinimalPlugins, NextState, PluginGroup},
+    scene::SceneSpawner,
+    state::app::StatesPlugin,
+    time::TimeUpdateStrategy,
+    transform::TransformPlugin,
+};
+
+use crate::{
+    menu::PongMenuPlugin,
+    replay::PongReplayPlugin,
+    rules::{GameRules, PongRulesPlugin},
+    structure::*,
+    visuals::PongVisualsPlugin,
+    PongGame,
+};
+
+// Simulated time per update. Matches the default fixed timestep so every update runs
+// exactly one FixedUpdate, no matter how fast the machine is
+pub const TICK: Duration = Duration::from_micros(15625);
+
+// Build an app that plays matches without a window, GPU, menus or recording, starting straight
+// in game
+// The rules are used as given instead of being loaded from the assets folder, and the same
+// rules, mode and seed always play out the same match
+pub fn headless_app(rules: GameRules, mode: GameMode, seed: u64) -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        StatesPlugin,
+        InputPlugin,
+        TransformPlugin,
+        HierarchyPlugin,
+        PongGame
+            .build()
+            .disable::<PongRulesPlugin>()
+            .disable::<PongMenuPlugin>()
+            .disable::<PongReplayPlugin>()
+            .disable::<PongVisualsPlugin>(),
+        PhysicsPlugins::new(FixedPostUpdate),
+    ))
+    .insert_resource(rules)
+    .insert_resource(mode)
+    .insert_resource(GameRng::new(seed))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(TICK));
+
+    // Avian looks for scenes when building colliders, there are none without the ScenePlugin
+    app.init_resource::<SceneSpawner>();
+
+    app.world_mut()
+        .resource_mut::<NextState<AppState>>()
+        .set(AppState::InGame);
+
+    // App::run would do this, but headless apps are stepped by hand with App::update
+    app.finish();
+    app.cleanup();
+    app
+}
+
+// Step the app until someone wins the match or max_ticks updates have passed
+pub fn run_match(app: &mut App, max_ticks: u32) -> Option<Scorer> {
+    let mut reader = ManualEventReader::<MatchWon>::default();
+
+    for _ in 0..max_ticks {
+        app.update();
+
+        let events = app.world().resource::<Events<MatchWon>>();
+        if let Some(MatchWon(winner)) = reader.read(events).next() {
+            return Some(*winner);
+        }
+    }
+
+    None
+}
@@ -0,0 +1,261 @@
+use std::{
+    fs, io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    app::{App, Update},
+    prelude::{
+        not, resource_exists, Commands, DetectChangesMut, IntoSystemConfigs, OnEnter, OnExit,
+        Plugin, Res, ResMut, Resource,
+    },
+    time::{Real, Time, Timer, TimerMode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    netcode::{NetSession, NetStatus},
+    rules::GameRules,
+    structure::*,
+};
+
+// Port hosts send their adverts to. Only the game browsing for matches listens on it
+pub const DISCOVERY_PORT: u16 = 7770;
+
+// How often a host advertises its match
+const ADVERT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Matches that haven't been advertised for this long are taken off the list
+const ADVERT_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub struct PongLanPlugin;
+
+impl Plugin for PongLanPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(AppState::LanGames), start_browsing);
+        app.add_systems(OnExit(AppState::LanGames), stop_browsing);
+        app.add_systems(
+            Update,
+            (
+                start_advertising
+                    .run_if(hosting)
+                    .run_if(not(resource_exists::<LanAdvertiser>)),
+                stop_advertising
+                    .run_if(not(hosting))
+                    .run_if(resource_exists::<LanAdvertiser>),
+                advertise_match
+                    .run_if(hosting)
+                    .run_if(resource_exists::<LanAdvertiser>),
+                browse_lan.run_if(resource_exists::<LanBrowser>),
+            ),
+        );
+    }
+}
+
+// What a host tells the rest of the network about its match
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LanAdvert {
+    pub id: u64, // Random per game, so a host heard on two addresses is only listed once
+    pub name: String,
+    pub port: u16, // Port the host's NetSession is listening on
+    pub rules: String,
+    pub score: Score,
+    pub in_progress: bool, // Already has an opponent
+}
+
+// A match heard from on the network
+#[derive(Clone, Debug)]
+pub struct LanGame {
+    pub addr: SocketAddr, // Where to join it
+    pub advert: LanAdvert,
+    last_seen: Instant,
+}
+
+// ##############################################################
+// # Advertising
+// ##############################################################
+
+// Broadcasts adverts while this game is hosting an online match. Only exists while it is, so
+// games that aren't hosting never open a socket for it
+#[derive(Resource)]
+pub struct LanAdvertiser {
+    socket: Option<UdpSocket>,
+    id: u64,
+    name: String,
+    timer: Timer,
+}
+
+impl LanAdvertiser {
+    fn bind() -> Self {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).and_then(|socket| {
+            socket.set_broadcast(true)?;
+            Ok(socket)
+        });
+        if let Err(err) = &socket {
+            eprintln!("Couldn't open a socket for LAN adverts: {err}");
+        }
+
+        Self {
+            socket: socket.ok(),
+            id: rand::random(),
+            name: host_name(),
+            timer: Timer::new(ADVERT_INTERVAL, TimerMode::Repeating),
+        }
+    }
+}
+
+// Only the host of an online match can be joined
+fn hosting(session: Option<Res<NetSession>>) -> bool {
+    session.is_some_and(|session| session.local_side == Side::Left)
+}
+
+fn start_advertising(mut commands: Commands) {
+    commands.insert_resource(LanAdvertiser::bind());
+}
+
+fn stop_advertising(mut commands: Commands) {
+    commands.remove_resource::<LanAdvertiser>();
+}
+
+fn advertise_match(
+    mut advertiser: ResMut<LanAdvertiser>,
+    session: Res<NetSession>,
+    rules: Res<GameRules>,
+    score: Res<Score>,
+    time: Res<Time<Real>>,
+) {
+    if !advertiser.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let (Some(socket), Some(port)) = (&advertiser.socket, session.local_port()) else {
+        return;
+    };
+
+    let advert = LanAdvert {
+        id: advertiser.id,
+        name: advertiser.name.clone(),
+        port,
+        rules: rules.summary(),
+        score: score.clone(),
+        in_progress: session.status != NetStatus::Connecting,
+    };
+    let bytes = ron::to_string(&advert).expect("adverts always serialize");
+
+    // Broadcasts don't reach other games on this machine, so tell loopback separately
+    for ip in [Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST] {
+        let _ = socket.send_to(bytes.as_bytes(), (ip, DISCOVERY_PORT));
+    }
+}
+
+// Best guess at a name people will recognise the machine by
+fn host_name() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| {
+            fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|name| name.trim().to_string())
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("Pong host"))
+}
+
+// ##############################################################
+// # Browsing
+// ##############################################################
+
+// Listens for adverts while the LAN games screen is open. Only marked as changed when the list
+// of games does, so the screen knows when to redraw it
+#[derive(Resource)]
+pub struct LanBrowser {
+    socket: Option<UdpSocket>,
+    pub error: Option<String>, // Why we can't listen for adverts
+    pub games: Vec<LanGame>,
+}
+
+impl LanBrowser {
+    pub fn bind() -> Self {
+        match Self::open_socket() {
+            Ok(socket) => Self {
+                socket: Some(socket),
+                error: None,
+                games: Vec::new(),
+            },
+            Err(err) => Self {
+                socket: None,
+                error: Some(format!("Can't look for LAN games: {err}")),
+                games: Vec::new(),
+            },
+        }
+    }
+
+    fn open_socket() -> Result<UdpSocket, io::Error> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+}
+
+fn start_browsing(mut commands: Commands) {
+    commands.insert_resource(LanBrowser::bind());
+}
+
+fn stop_browsing(mut commands: Commands) {
+    commands.remove_resource::<LanBrowser>();
+}
+
+fn browse_lan(mut browser: ResMut<LanBrowser>, advertiser: Option<Res<LanAdvertiser>>) {
+    let mut changed = false;
+    let lan = browser.bypass_change_detection();
+
+    if let Some(socket) = &lan.socket {
+        let mut buffer = [0; 2048];
+        while let Ok((len, from)) = socket.recv_from(&mut buffer) {
+            let Some(advert) = std::str::from_utf8(&buffer[..len])
+                .ok()
+                .and_then(|advert| ron::from_str::<LanAdvert>(advert).ok())
+            else {
+                continue;
+            };
+            // Don't list our own match
+            if advertiser
+                .as_ref()
+                .is_some_and(|advertiser| advert.id == advertiser.id)
+            {
+                continue;
+            }
+
+            let addr = SocketAddr::new(from.ip(), advert.port);
+            match lan
+                .games
+                .iter_mut()
+                .find(|game| game.advert.id == advert.id)
+            {
+                Some(game) => {
+                    changed |= game.advert != advert;
+                    game.advert = advert;
+                    game.last_seen = Instant::now();
+                }
+                None => {
+                    lan.games.push(LanGame {
+                        addr,
+                        advert,
+                        last_seen: Instant::now(),
+                    });
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let count = lan.games.len();
+    lan.games
+        .retain(|game| game.last_seen.elapsed() < ADVERT_TIMEOUT);
+    changed |= lan.games.len() != count;
+
+    if changed {
+        browser.set_changed();
+    }
+}
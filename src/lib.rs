@@ -0,0 +1,46 @@
+use bevy::{app::PluginGroupBuilder, prelude::PluginGroup};
+
+pub mod actors; // Stores behavior of active objects
+pub mod ai; // AI paddle controllers
+pub mod bot; // Paddles steered by external programs
+pub mod client; // Playing matches run by a pong-server
+pub mod controls; // Rebindable key bindings
+pub mod difficulty; // Adaptive AI difficulty
+pub mod env; // Gym-style environment for training agents
+pub mod gamestate;
+pub mod headless; // Running matches without a window or GPU
+pub mod lan; // Finding online matches on the local network
+pub mod menu; // Main, pause and settings menus
+pub mod netcode; // Online versus with rollback
+#[cfg(feature = "python")]
+mod python; // Python bindings for training, only with the python feature
+pub mod replay; // Recording matches and playing them back
+pub mod rules; // Runtime configurable gameplay tuning
+pub mod score; // Scores scoring elements
+pub mod server; // Dedicated server protocol, lobby and matches
+pub mod setup; // Contains initialization information
+pub mod spectate; // Broadcasting matches and watching them from elsewhere
+pub mod structure; // Stores Components, Events, Enums, and other building blocks // Stores core game functionality like collisions and internal-to-engine mapping
+pub mod visuals; // Sprites, meshes, camera and window
+
+pub struct PongGame;
+impl PluginGroup for PongGame {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(rules::PongRulesPlugin)
+            .add(setup::PongInitPlugin)
+            .add(score::PongScorePlugin)
+            .add(actors::PongActorPlugin)
+            .add(bot::PongBotPlugin)
+            .add(gamestate::PongGameStatePlugin)
+            .add(menu::PongMenuPlugin)
+            .add(controls::PongControlsPlugin)
+            .add(difficulty::PongDifficultyPlugin)
+            .add(replay::PongReplayPlugin)
+            .add(netcode::PongNetcodePlugin)
+            .add(lan::PongLanPlugin)
+            .add(client::PongClientPlugin)
+            .add(spectate::PongSpectatePlugin)
+            .add(visuals::PongVisualsPlugin)
+    }
+}
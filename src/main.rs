@@ -1,50 +1,207 @@
+use std::{net::SocketAddr, str::FromStr, time::Duration};
+
 use bevy::{
-    app::PluginGroupBuilder,
-    prelude::{default, App, DefaultPlugins, ImagePlugin, PluginGroup, Window, WindowPlugin},
+    asset::AssetPlugin,
+    prelude::{
+        default, App, DefaultPlugins, FixedPostUpdate, ImagePlugin, NextState, PluginGroup, Window,
+        WindowPlugin,
+    },
     window::{EnabledButtons, PresentMode},
 };
 
 use avian2d::prelude::PhysicsPlugins;
 
-mod actors; // Stores behavior of active objects
-mod gamestate;
-mod score; // Scores scoring elements
-mod setup; // Contains initialization information
-mod structure; // Stores Components, Events, Enums, and other building blocks // Stores core game functionality like collisions and internal-to-engine mapping
-
-struct PongGame;
-impl PluginGroup for PongGame {
-    fn build(self) -> PluginGroupBuilder {
-        PluginGroupBuilder::start::<Self>()
-            .add(setup::PongInitPlugin)
-            .add(score::PongScorePlugin)
-            .add(actors::PongActorPlugin)
-            .add(gamestate::PongGameStatePlugin)
-    }
-}
+use bevy_pong::{
+    bot, client, controls, headless, netcode, replay, rules, server, spectate, structure::*,
+    PongGame,
+};
+
+// Longest a --headless match can go before giving up, about half an hour of play
+const HEADLESS_MAX_TICKS: u32 = 64 * 60 * 30;
 
 fn main() {
-    App::new()
-        .add_plugins((
-            DefaultPlugins
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        title: String::from("Bevy Pong!"),
-                        name: Some(String::from("dev.mialikestech.bevypong")),
-                        resolution: (structure::WIN_WIDTH, structure::WIN_HEIGHT).into(),
-                        present_mode: PresentMode::AutoVsync,
-                        resizable: false,
-                        enabled_buttons: EnabledButtons {
-                            maximize: false,
-                            ..default()
-                        },
+    let rules = rules::GameRules::load(rules::RULES_ASSET).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+
+    let args: Vec<String> = std::env::args().collect();
+    let bots = bots(&args);
+
+    // Play a single AI vs AI match without a window and print the result. Bots take over
+    // whichever sides they're given
+    if args.iter().any(|arg| arg == "--headless") {
+        let seed = rand::random();
+        println!("Seed {seed}");
+
+        let mut app = headless::headless_app(rules, GameMode::AiVersusAi, seed);
+        if let Some(bots) = bots {
+            app.insert_resource(bots);
+        }
+        let winner = headless::run_match(&mut app, HEADLESS_MAX_TICKS);
+        let score = app.world().resource::<Score>();
+
+        match winner {
+            Some(Scorer::Ai) => println!("Left wins {} - {}", score.ai, score.player),
+            Some(Scorer::Player) => println!("Right wins {} - {}", score.player, score.ai),
+            None => {
+                eprintln!("No winner after {HEADLESS_MAX_TICKS} ticks");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // List the matches waiting for an opponent on a pong-server
+    if let Some(addr) = arg_value::<SocketAddr>(&args, "--matches") {
+        match server::open_matches(addr, Duration::from_secs(5)) {
+            Ok(codes) if codes.is_empty() => println!("No open matches on {addr}"),
+            Ok(codes) => println!("Open matches on {addr}: {}", codes.join(", ")),
+            Err(err) => {
+                eprintln!("Couldn't list matches on {addr}: {err}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Play back a recorded match instead of starting at the menu
+    let replay = args.iter().position(|arg| arg == "--replay").map(|index| {
+        let path = args
+            .get(index + 1)
+            .map_or(replay::REPLAY_PATH, String::as_str);
+        replay::Replay::load(path).unwrap_or_else(|err| {
+            eprintln!("Couldn't load replay {path}: {err}");
+            std::process::exit(1);
+        })
+    });
+    let rules = replay.as_ref().map_or(rules, |replay| replay.rules.clone());
+
+    let mut app = App::new();
+    app.add_plugins((
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    title: String::from("Bevy Pong!"),
+                    name: Some(String::from("dev.mialikestech.bevypong")),
+                    resolution: (rules.field_width, rules.field_height).into(),
+                    present_mode: PresentMode::AutoVsync,
+                    resizable: false,
+                    enabled_buttons: EnabledButtons {
+                        maximize: false,
                         ..default()
-                    }),
+                    },
                     ..default()
-                })
-                .set(ImagePlugin::default_nearest()),
-            PongGame,
-        ))
-        .add_plugins(PhysicsPlugins::default())
-        .run();
+                }),
+                ..default()
+            })
+            .set(ImagePlugin::default_nearest())
+            // Pick up edits to the rules file while the game is running
+            .set(AssetPlugin {
+                watch_for_changes_override: Some(true),
+                ..default()
+            }),
+        PongGame,
+    ))
+    .insert_resource(rules)
+    .insert_resource(controls::InputMap::load(controls::CONTROLS_PATH))
+    .add_plugins(PhysicsPlugins::new(FixedPostUpdate));
+
+    // Host or join an online match, with an optional bad connection for testing
+    let net_config = netcode::NetConfig {
+        input_delay: arg_value(&args, "--input-delay").unwrap_or(2),
+        latency: Duration::from_millis(arg_value(&args, "--latency").unwrap_or(0)),
+        jitter: Duration::from_millis(arg_value(&args, "--jitter").unwrap_or(0)),
+        loss: arg_value::<f32>(&args, "--loss").unwrap_or(0.) / 100.,
+        ..default()
+    };
+    let session = if let Some(port) = arg_value(&args, "--host") {
+        Some(netcode::NetSession::host(port, net_config))
+    } else {
+        arg_value(&args, "--join").map(|addr| netcode::NetSession::join(addr, net_config))
+    };
+    match session {
+        Some(Ok(session)) => {
+            app.insert_resource(session);
+        }
+        Some(Err(err)) => {
+            eprintln!("Couldn't open a network socket: {err}");
+            std::process::exit(1);
+        }
+        None => (),
+    }
+
+    // Play a match run by a pong-server, hosting a new one unless given a code to join
+    if let Some(addr) = arg_value::<SocketAddr>(&args, "--server") {
+        let connection = match arg_value::<String>(&args, "--code") {
+            Some(code) => client::ServerConnection::join(addr, &code),
+            None => client::ServerConnection::host(addr),
+        };
+        app.insert_resource(connection.unwrap_or_else(|err| {
+            eprintln!("Couldn't connect to {addr}: {err}");
+            std::process::exit(1);
+        }));
+    }
+
+    // Stream matches played here to spectators, or watch someone else's stream
+    if let Some(port) = arg_value(&args, "--broadcast") {
+        app.insert_resource(
+            spectate::SpectatorBroadcast::bind(port).unwrap_or_else(|err| {
+                eprintln!("Couldn't broadcast on port {port}: {err}");
+                std::process::exit(1);
+            }),
+        );
+    }
+    if let Some(addr) = arg_value::<SocketAddr>(&args, "--spectate") {
+        let delay = Duration::from_millis(arg_value(&args, "--spectate-delay").unwrap_or(2000));
+        app.insert_resource(
+            spectate::Spectating::connect(addr, delay).unwrap_or_else(|err| {
+                eprintln!("Couldn't connect to {addr}: {err}");
+                std::process::exit(1);
+            }),
+        );
+    }
+
+    if let Some(bots) = bots {
+        app.insert_resource(bots);
+    }
+
+    if let Some(replay) = replay {
+        app.insert_resource(replay.mode)
+            .insert_resource(GameRng::new(replay.seed))
+            .insert_resource(replay::Playback::new(replay));
+        app.world_mut()
+            .resource_mut::<NextState<AppState>>()
+            .set(AppState::InGame);
+    }
+
+    app.run();
+}
+
+// Start the bots given with --bot-left and --bot-right, if there are any
+fn bots(args: &[String]) -> Option<bot::Bots> {
+    let timeout =
+        arg_value(args, "--bot-timeout").map_or(bot::DEFAULT_BOT_TIMEOUT, Duration::from_millis);
+    let mut bots = bot::Bots::new(timeout);
+
+    for (side, flag) in [(Side::Left, "--bot-left"), (Side::Right, "--bot-right")] {
+        if let Some(spec) = arg_value::<String>(args, flag) {
+            if let Err(err) = bots.add(side, &spec) {
+                eprintln!("Couldn't start bot {spec}: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    (bots.has(Side::Left) || bots.has(Side::Right)).then_some(bots)
+}
+
+// Parse the value following a flag, exiting if it's there but invalid
+fn arg_value<T: FromStr>(args: &[String], flag: &str) -> Option<T> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    let Some(value) = args.get(index + 1).and_then(|value| value.parse().ok()) else {
+        eprintln!("{flag} needs a valid value");
+        std::process::exit(1);
+    };
+    Some(value)
 }
@@ -0,0 +1,663 @@
+use bevy::{
+    app::{App, AppExit, Update},
+    ecs::{
+        query::Changed,
+        schedule::{Condition, IntoSystemConfigs},
+    },
+    hierarchy::{BuildChildren, ChildBuilder, DespawnRecursiveExt},
+    input::ButtonInput,
+    prelude::{
+        any_with_component, default, in_state, resource_changed, ButtonBundle, Color, Commands,
+        Component, DetectChanges, Entity, EventWriter, JustifyText, KeyCode, NextState, NodeBundle,
+        OnEnter, Plugin, Query, Ref, Res, ResMut, Resource, StateScoped, Text, TextBundle,
+        TextSection, TextStyle, With, Without,
+    },
+    ui::{
+        AlignItems, BackgroundColor, FlexDirection, Interaction, JustifyContent, Style, Val, ZIndex,
+    },
+};
+
+use crate::{
+    ai::AiPersonality,
+    controls::InputMap,
+    lan::{LanBrowser, LanGame},
+    netcode::{NetConfig, NetSession},
+    rules::GameRules,
+    structure::*,
+};
+
+const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+const SELECTED_BUTTON: Color = Color::srgb(0.35, 0.35, 0.35);
+const OVERLAY: Color = Color::srgba(0., 0., 0., 0.6);
+
+pub struct PongMenuPlugin;
+
+impl Plugin for PongMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MenuSelection>();
+        app.init_resource::<Rebinding>();
+        app.add_systems(OnEnter(AppState::MainMenu), spawn_main_menu);
+        app.add_systems(OnEnter(AppState::Settings), spawn_settings_menu);
+        app.add_systems(OnEnter(AppState::Controls), spawn_controls_menu);
+        app.add_systems(OnEnter(AppState::LanGames), spawn_lan_menu);
+        app.add_systems(OnEnter(PauseState::Paused), spawn_pause_menu);
+        // Escape has to work on screens that have no entries yet
+        app.add_systems(
+            Update,
+            (
+                leave_submenu.run_if(
+                    in_state(AppState::Settings)
+                        .or_else(in_state(AppState::Controls))
+                        .or_else(in_state(AppState::LanGames)),
+                ),
+                capture_rebind.run_if(in_state(AppState::Controls)),
+            )
+                .chain()
+                .before(hover_menu)
+                .before(navigate_menu),
+        );
+        app.add_systems(
+            Update,
+            (
+                (hover_menu, navigate_menu),
+                (activate_menu, adjust_setting, activate_controls),
+                highlight_menu,
+            )
+                .chain()
+                .run_if(any_with_component::<MenuButton>),
+        );
+        app.add_systems(
+            Update,
+            (
+                update_setting_labels.run_if(in_state(AppState::Settings)),
+                update_binding_labels.run_if(in_state(AppState::Controls)),
+                update_lan_list
+                    .run_if(in_state(AppState::LanGames))
+                    .run_if(resource_changed::<LanBrowser>),
+            ),
+        );
+    }
+}
+
+// Index of the currently highlighted MenuButton
+#[derive(Resource, Default)]
+struct MenuSelection(usize);
+
+// Text of a settings entry, kept in sync with GameRules
+#[derive(Component)]
+struct SettingLabel(Setting);
+
+// Binding waiting for a key press on the controls screen, if any
+#[derive(Resource, Default)]
+struct Rebinding(Option<(Side, Action)>);
+
+// Text of a controls entry, kept in sync with the InputMap
+#[derive(Component)]
+struct BindingLabel(Side, Action);
+
+// Text of the mouse/touch control toggle
+#[derive(Component)]
+struct PointerLabel;
+
+// Holds the entries on the LAN games screen, rebuilt whenever the list changes
+#[derive(Component)]
+struct LanGameList;
+
+// Back entry under the LAN games list, there even when no games have been found
+#[derive(Component)]
+struct LanBackButton;
+
+// ##############################################################
+// # Menu Screens
+// ##############################################################
+
+// Spawn the title screen
+fn spawn_main_menu(mut commands: Commands, mut selection: ResMut<MenuSelection>) {
+    selection.0 = 0;
+
+    commands
+        .spawn((menu_root(Color::NONE), StateScoped(AppState::MainMenu)))
+        .with_children(|parent| {
+            spawn_title(parent, "Bevy Pong!");
+            spawn_button(parent, 0, MenuAction::Play(GameMode::VsAi), "Play vs AI");
+            spawn_button(
+                parent,
+                1,
+                MenuAction::Play(GameMode::LocalVersus),
+                "Local 2P",
+            );
+            spawn_button(parent, 2, MenuAction::LanGames, "Join LAN game");
+            spawn_button(parent, 3, MenuAction::Settings, "Settings");
+            spawn_button(parent, 4, MenuAction::Controls, "Controls");
+            spawn_button(parent, 5, MenuAction::Quit, "Quit");
+        });
+}
+
+// Spawn the settings screen, values are changed with left and right
+fn spawn_settings_menu(
+    mut commands: Commands,
+    mut selection: ResMut<MenuSelection>,
+    rules: Res<GameRules>,
+) {
+    selection.0 = 0;
+
+    commands
+        .spawn((menu_root(Color::NONE), StateScoped(AppState::Settings)))
+        .with_children(|parent| {
+            spawn_title(parent, "Settings");
+            let settings = [
+                Setting::BallSpeed,
+                Setting::PaddleHeight,
+                Setting::AiSkill,
+                Setting::AiPersonality,
+            ];
+            for (index, setting) in settings.into_iter().enumerate() {
+                parent
+                    .spawn(button(index, MenuAction::Setting(setting)))
+                    .with_children(|button| {
+                        button.spawn((
+                            TextBundle::from_section(setting_text(setting, &rules), text_style()),
+                            SettingLabel(setting),
+                        ));
+                    });
+            }
+            spawn_button(parent, settings.len(), MenuAction::MainMenu, "Back");
+        });
+}
+
+// Spawn the rebind screen, one entry per player and action
+fn spawn_controls_menu(
+    mut commands: Commands,
+    mut selection: ResMut<MenuSelection>,
+    mut rebinding: ResMut<Rebinding>,
+    input_map: Res<InputMap>,
+) {
+    selection.0 = 0;
+    rebinding.0 = None;
+
+    let bindings = [Side::Left, Side::Right].into_iter().flat_map(|side| {
+        [
+            Action::MoveUp,
+            Action::MoveDown,
+            Action::Serve,
+            Action::Pause,
+        ]
+        .map(|action| (side, action))
+    });
+
+    commands
+        .spawn((menu_root(Color::NONE), StateScoped(AppState::Controls)))
+        .with_children(|parent| {
+            spawn_title(parent, "Controls");
+            let mut index = 0;
+            for (side, action) in bindings {
+                parent
+                    .spawn(button(index, MenuAction::Rebind(side, action)))
+                    .with_children(|button| {
+                        button.spawn((
+                            TextBundle::from_section(
+                                binding_text(side, action, &input_map, false),
+                                text_style(),
+                            ),
+                            BindingLabel(side, action),
+                        ));
+                    });
+                index += 1;
+            }
+            parent
+                .spawn(button(index, MenuAction::TogglePointer))
+                .with_children(|button| {
+                    button.spawn((
+                        TextBundle::from_section(pointer_text(&input_map), text_style()),
+                        PointerLabel,
+                    ));
+                });
+            spawn_button(parent, index + 1, MenuAction::MainMenu, "Back");
+        });
+}
+
+// Spawn the LAN games screen, the entries are filled in by update_lan_list as games are found
+fn spawn_lan_menu(mut commands: Commands, mut selection: ResMut<MenuSelection>) {
+    selection.0 = 0;
+
+    commands
+        .spawn((menu_root(Color::NONE), StateScoped(AppState::LanGames)))
+        .with_children(|parent| {
+            spawn_title(parent, "LAN Games");
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        row_gap: Val::Px(10.),
+                        ..default()
+                    },
+                    ..default()
+                },
+                LanGameList,
+            ));
+            parent
+                .spawn((button(0, MenuAction::MainMenu), LanBackButton))
+                .with_children(|button| {
+                    button.spawn(TextBundle::from_section("Back", text_style()));
+                });
+        });
+}
+
+// Spawn the pause overlay on top of the running match
+fn spawn_pause_menu(mut commands: Commands, mut selection: ResMut<MenuSelection>) {
+    selection.0 = 0;
+
+    commands
+        .spawn((menu_root(OVERLAY), StateScoped(PauseState::Paused)))
+        .with_children(|parent| {
+            spawn_title(parent, "Paused");
+            spawn_button(parent, 0, MenuAction::Resume, "Resume");
+            spawn_button(parent, 1, MenuAction::MainMenu, "Main Menu");
+        });
+}
+
+// ##############################################################
+// # Menu Navigation
+// ##############################################################
+
+// Move the selection with the arrow keys or W/S, wrapping around at either end
+fn navigate_menu(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    buttons: Query<&MenuButton>,
+    mut selection: ResMut<MenuSelection>,
+) {
+    let count = buttons.iter().count();
+
+    if keyboard_input.any_just_pressed([KeyCode::ArrowDown, KeyCode::KeyS]) {
+        selection.0 = (selection.0 + 1) % count;
+    } else if keyboard_input.any_just_pressed([KeyCode::ArrowUp, KeyCode::KeyW]) {
+        selection.0 = (selection.0 + count - 1) % count;
+    }
+}
+
+// Let the mouse pick entries too
+fn hover_menu(
+    buttons: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut selection: ResMut<MenuSelection>,
+) {
+    for (interaction, button) in &buttons {
+        if *interaction != Interaction::None {
+            selection.0 = button.index;
+        }
+    }
+}
+
+// Run the selected entry's action on Enter/Space or a click
+fn activate_menu(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    buttons: Query<(&MenuButton, Ref<Interaction>)>,
+    selection: Res<MenuSelection>,
+    mut app_state: ResMut<NextState<AppState>>,
+    mut pause_state: ResMut<NextState<PauseState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let confirmed = keyboard_input.any_just_pressed([KeyCode::Enter, KeyCode::Space]);
+
+    for (button, interaction) in &buttons {
+        // Only the frame the click lands, holding the button down shouldn't keep activating it
+        let clicked = *interaction == Interaction::Pressed && interaction.is_changed();
+        if !(clicked || (confirmed && button.index == selection.0)) {
+            continue;
+        }
+
+        match button.action {
+            MenuAction::Play(mode) => {
+                commands.insert_resource(mode);
+                commands.insert_resource(GameRng::default());
+                app_state.set(AppState::InGame);
+            }
+            // The match starts once the host lets us in
+            MenuAction::JoinLan(addr) => match NetSession::join(addr, NetConfig::default()) {
+                Ok(session) => commands.insert_resource(session),
+                Err(err) => eprintln!("Couldn't open a network socket: {err}"),
+            },
+            MenuAction::LanGames => app_state.set(AppState::LanGames),
+            MenuAction::Settings => app_state.set(AppState::Settings),
+            MenuAction::Controls => app_state.set(AppState::Controls),
+            MenuAction::Quit => {
+                exit.send(AppExit::Success);
+            }
+            MenuAction::Resume => pause_state.set(PauseState::Running),
+            MenuAction::MainMenu => app_state.set(AppState::MainMenu),
+            MenuAction::Setting(_) | MenuAction::Rebind(..) | MenuAction::TogglePointer => (),
+        }
+    }
+}
+
+// Change the selected setting with left/right or A/D
+fn adjust_setting(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    buttons: Query<&MenuButton>,
+    selection: Res<MenuSelection>,
+    mut rules: ResMut<GameRules>,
+) {
+    let steps = if keyboard_input.any_just_pressed([KeyCode::ArrowRight, KeyCode::KeyD]) {
+        1.
+    } else if keyboard_input.any_just_pressed([KeyCode::ArrowLeft, KeyCode::KeyA]) {
+        -1.
+    } else {
+        return;
+    };
+
+    for button in &buttons {
+        if let MenuAction::Setting(setting) = button.action {
+            if button.index == selection.0 {
+                // Steps that would break the rules, like a ball too fast for the paddles to
+                // stop at the top speed tier, are ignored
+                let mut edited = rules.clone();
+                adjust(setting, &mut edited, steps);
+                if edited.validate().is_ok() {
+                    *rules = edited;
+                }
+            }
+        }
+    }
+}
+
+// Handle entries on the controls screen: bindings wait for a new key, and the
+// pointer entry flips mouse/touch control
+fn activate_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    buttons: Query<(&MenuButton, Ref<Interaction>)>,
+    selection: Res<MenuSelection>,
+    mut rebinding: ResMut<Rebinding>,
+    mut input_map: ResMut<InputMap>,
+) {
+    let confirmed = keyboard_input.any_just_pressed([KeyCode::Enter, KeyCode::Space]);
+
+    for (button, interaction) in &buttons {
+        let clicked = *interaction == Interaction::Pressed && interaction.is_changed();
+        if !(clicked || (confirmed && button.index == selection.0)) {
+            continue;
+        }
+
+        match button.action {
+            MenuAction::Rebind(side, action) => rebinding.0 = Some((side, action)),
+            MenuAction::TogglePointer => input_map.pointer_control = !input_map.pointer_control,
+            _ => (),
+        }
+    }
+}
+
+// Bind the next key pressed, and swallow it so it doesn't also navigate the menu
+fn capture_rebind(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut input_map: ResMut<InputMap>,
+) {
+    let Some((side, action)) = rebinding.0 else {
+        return;
+    };
+
+    let pressed = keyboard_input.get_just_pressed().next().copied();
+    if let Some(key) = pressed {
+        input_map.side_mut(side).set(action, key);
+        keyboard_input.clear_just_pressed(key);
+        rebinding.0 = None;
+    }
+}
+
+// Brighten whichever entry is selected
+fn highlight_menu(
+    mut buttons: Query<(&MenuButton, &mut BackgroundColor)>,
+    selection: Res<MenuSelection>,
+) {
+    for (button, mut color) in &mut buttons {
+        color.0 = if button.index == selection.0 {
+            SELECTED_BUTTON
+        } else {
+            NORMAL_BUTTON
+        };
+    }
+}
+
+// Redraw the settings values after they get changed
+fn update_setting_labels(mut labels: Query<(&mut Text, &SettingLabel)>, rules: Res<GameRules>) {
+    if rules.is_changed() {
+        for (mut text, SettingLabel(setting)) in &mut labels {
+            text.sections[0].value = setting_text(*setting, &rules);
+        }
+    }
+}
+
+// Redraw the bindings after they get changed, or while one waits for a key
+fn update_binding_labels(
+    mut labels: Query<(&mut Text, &BindingLabel)>,
+    mut pointer_label: Query<&mut Text, (With<PointerLabel>, Without<BindingLabel>)>,
+    input_map: Res<InputMap>,
+    rebinding: Res<Rebinding>,
+) {
+    if input_map.is_changed() || rebinding.is_changed() {
+        for (mut text, BindingLabel(side, action)) in &mut labels {
+            let waiting = rebinding.0 == Some((*side, *action));
+            text.sections[0].value = binding_text(*side, *action, &input_map, waiting);
+        }
+        if let Ok(mut text) = pointer_label.get_single_mut() {
+            text.sections[0].value = pointer_text(&input_map);
+        }
+    }
+}
+
+// Rebuild the LAN games list. Games waiting for an opponent can be joined, ones already being
+// played are only shown
+fn update_lan_list(
+    mut commands: Commands,
+    list: Query<Entity, With<LanGameList>>,
+    mut back: Query<&mut MenuButton, With<LanBackButton>>,
+    browser: Res<LanBrowser>,
+    mut selection: ResMut<MenuSelection>,
+) {
+    let Ok(list) = list.get_single() else {
+        return;
+    };
+
+    commands.entity(list).despawn_descendants();
+    commands.entity(list).with_children(|parent| {
+        let status = if let Some(error) = &browser.error {
+            Some(error.as_str())
+        } else if browser.games.is_empty() {
+            Some("Looking for games...")
+        } else {
+            None
+        };
+        if let Some(status) = status {
+            parent.spawn(TextBundle::from_section(status, text_style()));
+        }
+
+        let mut index = 0;
+        for game in &browser.games {
+            if game.advert.in_progress {
+                parent.spawn(lan_game_text(game));
+            } else {
+                let (mut entry, action) = button(index, MenuAction::JoinLan(game.addr));
+                entry.style.width = Val::Px(640.);
+                entry.style.height = Val::Px(72.);
+                parent.spawn((entry, action)).with_children(|button| {
+                    button.spawn(lan_game_text(game));
+                });
+                index += 1;
+            }
+        }
+
+        // Back stays last as games come and go
+        for mut back in &mut back {
+            back.index = index;
+        }
+        selection.0 = selection.0.min(index);
+    });
+}
+
+// Escape backs out of the settings, controls and LAN games screens, unless a key is being
+// bound
+fn leave_submenu(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    rebinding: Res<Rebinding>,
+    mut app_state: ResMut<NextState<AppState>>,
+) {
+    if rebinding.0.is_none() && keyboard_input.just_pressed(KeyCode::Escape) {
+        app_state.set(AppState::MainMenu);
+    }
+}
+
+// ##############################################################
+// # Settings
+// ##############################################################
+
+// Step a setting up or down, keeping it within sensible bounds
+fn adjust(setting: Setting, rules: &mut GameRules, steps: f32) {
+    match setting {
+        Setting::BallSpeed => {
+            rules.ball_speed = (rules.ball_speed + steps * 25.).clamp(100., 600.);
+        }
+        Setting::PaddleHeight => {
+            // Keep the bounds in order on fields too short for the smallest paddle, adjust_setting
+            // then turns the step down since the paddle wouldn't fit
+            let max = (rules.play_height() - 10.).clamp(20., 200.);
+            rules.paddle_height = (rules.paddle_height + steps * 10.).clamp(20., max);
+        }
+        Setting::AiSkill => {
+            rules.ai_skill = (rules.ai_skill + steps).clamp(1., 40.);
+        }
+        Setting::AiPersonality => {
+            // Cycle through the personalities, wrapping around at either end
+            let all = AiPersonality::ALL;
+            let current = all.iter().position(|p| *p == rules.ai_personality);
+            let next =
+                (current.unwrap_or(0) as isize + steps as isize).rem_euclid(all.len() as isize);
+            rules.ai_personality = all[next as usize];
+        }
+    }
+}
+
+fn setting_text(setting: Setting, rules: &GameRules) -> String {
+    match setting {
+        Setting::BallSpeed => format!("< Ball speed: {} >", rules.ball_speed),
+        Setting::PaddleHeight => format!("< Paddle size: {} >", rules.paddle_height),
+        Setting::AiSkill => format!("< AI skill: {} >", rules.ai_skill),
+        Setting::AiPersonality => format!("< AI: {} >", rules.ai_personality.name()),
+    }
+}
+
+fn binding_text(side: Side, action: Action, input_map: &InputMap, waiting: bool) -> String {
+    let side_name = match side {
+        Side::Left => "Left",
+        Side::Right => "Right",
+    };
+    let action_name = match action {
+        Action::MoveUp => "up",
+        Action::MoveDown => "down",
+        Action::Serve => "serve",
+        Action::Pause => "pause",
+    };
+
+    if waiting {
+        format!("{side_name} {action_name}: press a key...")
+    } else {
+        let key = input_map.side(side).get(action);
+        format!("{side_name} {action_name}: {key:?}")
+    }
+}
+
+// Host name and score on top, rules underneath
+fn lan_game_text(game: &LanGame) -> TextBundle {
+    let advert = &game.advert;
+    let status = if advert.in_progress { " (playing)" } else { "" };
+
+    TextBundle::from_sections([
+        TextSection::new(
+            format!(
+                "{}: {} - {}{status}\n",
+                advert.name, advert.score.player, advert.score.ai
+            ),
+            text_style(),
+        ),
+        TextSection::new(
+            advert.rules.clone(),
+            TextStyle {
+                font_size: 20.,
+                ..text_style()
+            },
+        ),
+    ])
+    .with_text_justify(JustifyText::Center)
+}
+
+fn pointer_text(input_map: &InputMap) -> String {
+    let state = if input_map.pointer_control {
+        "On"
+    } else {
+        "Off"
+    };
+    format!("Mouse/touch control: {state}")
+}
+
+// ##############################################################
+// # Widgets
+// ##############################################################
+
+// Full screen column that centers its children
+fn menu_root(background: Color) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            row_gap: Val::Px(10.),
+            ..default()
+        },
+        background_color: background.into(),
+        z_index: ZIndex::Global(1),
+        ..default()
+    }
+}
+
+fn button(index: usize, action: MenuAction) -> (ButtonBundle, MenuButton) {
+    (
+        ButtonBundle {
+            style: Style {
+                width: Val::Px(320.),
+                height: Val::Px(40.),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            background_color: NORMAL_BUTTON.into(),
+            ..default()
+        },
+        MenuButton { index, action },
+    )
+}
+
+fn spawn_button(parent: &mut ChildBuilder, index: usize, action: MenuAction, label: &str) {
+    parent.spawn(button(index, action)).with_children(|button| {
+        button.spawn(TextBundle::from_section(label, text_style()));
+    });
+}
+
+fn spawn_title(parent: &mut ChildBuilder, title: &str) {
+    parent.spawn(TextBundle::from_section(
+        title,
+        TextStyle {
+            font_size: 64.,
+            color: Color::WHITE,
+            ..default()
+        },
+    ));
+}
+
+fn text_style() -> TextStyle {
+    TextStyle {
+        font_size: 32.,
+        color: Color::WHITE,
+        ..default()
+    }
+}
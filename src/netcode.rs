@@ -0,0 +1,828 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use avian2d::prelude::{AngularVelocity, Collisions, LinearVelocity};
+use bevy::{
+    app::{App, FixedUpdate, PreUpdate},
+    ecs::{event::Events, query::Or},
+    input::InputSystem,
+    prelude::{
+        in_state, resource_exists, Commands, Entity, GlobalTransform, IntoSystemConfigs, Mut,
+        NextState, OnEnter, OnExit, Plugin, Res, ResMut, Resource, State, Transform, With, World,
+    },
+    state::state::StateTransition,
+    time::{Time, Virtual},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    actors::sample_pointer_input, difficulty::AdaptiveDifficulty, gamestate::run_fixed_tick,
+    replay::Fnv1a, rules::GameRules, structure::*,
+};
+
+// Furthest the simulation may run ahead of the peer's inputs before it waits for them
+const MAX_PREDICTION: u64 = 12;
+
+// Ticks of inputs and snapshots kept around for rollbacks and resending
+const HISTORY: u64 = 128;
+
+// Ticks between checksums compared with the peer
+const CHECKSUM_INTERVAL: u64 = 32;
+
+// Most local inputs sent in one packet. Resent every frame until the peer acknowledges them
+const MAX_INPUTS_PER_PACKET: usize = 64;
+
+pub struct PongNetcodePlugin;
+
+impl Plugin for PongNetcodePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            (
+                capture_local_input.run_if(in_state(PauseState::Running)),
+                exchange_inputs,
+            )
+                .chain()
+                .after(InputSystem)
+                .after(sample_pointer_input)
+                .run_if(resource_exists::<NetSession>),
+        );
+        app.add_systems(
+            FixedUpdate,
+            net_tick
+                .in_set(GameplaySet::Sync)
+                .run_if(resource_exists::<NetSession>)
+                .run_if(in_state(AppState::InGame)),
+        );
+        app.add_systems(OnExit(AppState::InGame), end_session);
+        // A session that never connected mustn't take over whatever the player does instead
+        app.add_systems(OnExit(AppState::LanGames), cancel_join);
+        app.add_systems(OnEnter(AppState::InGame), cancel_connecting);
+    }
+}
+
+// ##############################################################
+// # Session
+// ##############################################################
+
+// How to reach the peer, and how bad to make the connection for testing
+#[derive(Clone, Debug)]
+pub struct NetConfig {
+    pub input_delay: u64, // Ticks local input waits before it's used, hiding that much latency
+    pub latency: Duration, // Added to every packet sent, for testing over loopback
+    pub jitter: Duration, // Random extra latency up to this much
+    pub loss: f32,        // Fraction of packets dropped, between 0 and 1
+    pub peer_timeout: Duration, // How long the peer can go quiet before the match is abandoned
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            input_delay: 2,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.,
+            peer_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetStatus {
+    Connecting,    // Host waiting for someone to join, or joiner waiting to be let in
+    Running,       // Match in progress
+    Desynced(u64), // Checksums stopped matching at this tick, the match can't be trusted
+}
+
+// A rollback networked match between this game and one peer
+// The host plays the left paddle and picks the seed and rules, the joiner plays the right
+#[derive(Resource)]
+pub struct NetSession {
+    pub config: NetConfig,
+    pub local_side: Side,
+    pub status: NetStatus,
+    socket: UdpSocket,
+    link: LinkConditioner,
+    peer: Option<SocketAddr>,
+    pending_local: SideInput, // Sampled since the last tick
+    local_inputs: BTreeMap<u64, SideInput>,
+    remote_inputs: BTreeMap<u64, SideInput>,
+    remote_confirmed: u64, // Every peer input before this tick has arrived
+    peer_ack: u64,         // The peer has every local input before this tick
+    predicted: BTreeMap<u64, SideInput>, // Peer input each tick was simulated with
+    snapshots: BTreeMap<u64, Snapshot>,
+    checksums: BTreeMap<u64, u64>,
+    remote_checksums: BTreeMap<u64, u64>,
+    resimulating: bool,
+    last_heard: Instant, // Last packet from the peer, or when the match started
+}
+
+impl NetSession {
+    // Wait for a peer on the given port
+    pub fn host(port: u16, config: NetConfig) -> Result<Self, io::Error> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        println!("Hosting on port {port}, waiting for a peer...");
+        Self::new(socket, None, Side::Left, config)
+    }
+
+    // Ask the host at addr to let us in
+    pub fn join(addr: SocketAddr, config: NetConfig) -> Result<Self, io::Error> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        println!("Joining {addr}...");
+        Self::new(socket, Some(addr), Side::Right, config)
+    }
+
+    fn new(
+        socket: UdpSocket,
+        peer: Option<SocketAddr>,
+        local_side: Side,
+        config: NetConfig,
+    ) -> Result<Self, io::Error> {
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            link: LinkConditioner::new(&config),
+            config,
+            local_side,
+            status: NetStatus::Connecting,
+            socket,
+            peer,
+            pending_local: SideInput::default(),
+            local_inputs: BTreeMap::new(),
+            remote_inputs: BTreeMap::new(),
+            remote_confirmed: 0,
+            peer_ack: 0,
+            predicted: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+            checksums: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            resimulating: false,
+            last_heard: Instant::now(),
+        })
+    }
+
+    // Port the session is listening on, for advertising it
+    pub fn local_port(&self) -> Option<u16> {
+        self.socket.local_addr().ok().map(|addr| addr.port())
+    }
+
+    // Clear all per-match state, with local input for the first input_delay ticks left empty
+    fn start(&mut self) {
+        self.status = NetStatus::Running;
+        self.local_inputs = (0..self.config.input_delay)
+            .map(|tick| (tick, SideInput::default()))
+            .collect();
+        self.remote_inputs.clear();
+        self.remote_confirmed = 0;
+        self.peer_ack = 0;
+        self.predicted.clear();
+        self.snapshots.clear();
+        self.checksums.clear();
+        self.remote_checksums.clear();
+        self.last_heard = Instant::now();
+    }
+
+    // Whether the peer has gone quiet for too long, or a host never answered our join
+    // Hosts wait as long as it takes for someone to join them
+    fn timed_out(&self) -> bool {
+        let hosting = self.status == NetStatus::Connecting && self.local_side == Side::Left;
+        !hosting && self.last_heard.elapsed() > self.config.peer_timeout
+    }
+
+    // Peer input for a tick, guessing it's the same as the last one we know if it hasn't arrived
+    fn remote_input(&self, tick: u64) -> SideInput {
+        if let Some(input) = self.remote_inputs.get(&tick) {
+            return *input;
+        }
+        self.remote_inputs
+            .range(..tick)
+            .next_back()
+            .map_or(SideInput::default(), |(_, input)| SideInput {
+                movement: input.movement,
+                serve: false,
+            })
+    }
+
+    fn send(&mut self, packet: &Packet) {
+        if let Some(peer) = self.peer {
+            self.link.send(&self.socket, peer, packet.to_bytes());
+        }
+    }
+
+    // Drop history nothing can roll back to anymore
+    fn prune(&mut self, tick: u64) {
+        let oldest = tick.saturating_sub(HISTORY);
+        for map in [
+            &mut self.local_inputs,
+            &mut self.remote_inputs,
+            &mut self.predicted,
+        ] {
+            *map = map.split_off(&oldest);
+        }
+        self.snapshots = self.snapshots.split_off(&oldest);
+        self.checksums = self.checksums.split_off(&oldest);
+        self.remote_checksums = self.remote_checksums.split_off(&oldest);
+    }
+}
+
+// ##############################################################
+// # Snapshots
+// ##############################################################
+
+// Everything a tick can change, taken at the start of the tick so it can be replayed from there
+struct Snapshot {
+    bodies: Vec<BodyState>,
+    score: Score,
+    serve: Serve,
+    rally: Rally,
+    phase_timer: PhaseTimer,
+    match_state: MatchState,
+    rng: GameRng,
+    collisions: Collisions, // Contacts reflect_ball handles at the start of the tick
+    difficulty: AdaptiveDifficulty, // So resimulated points aren't recorded twice
+}
+
+struct BodyState {
+    entity: Entity,
+    transform: Transform,
+    linear_velocity: Option<LinearVelocity>,
+    angular_velocity: Option<AngularVelocity>,
+    velocity: Option<Velocity>,
+}
+
+impl Snapshot {
+    fn take(world: &mut World) -> Option<Self> {
+        let mut bodies = world.query_filtered::<(
+            Entity,
+            &Transform,
+            Option<&LinearVelocity>,
+            Option<&AngularVelocity>,
+            Option<&Velocity>,
+        ), Or<(With<Ball>, With<Paddle>)>>();
+
+        let bodies = bodies
+            .iter(world)
+            .map(
+                |(entity, transform, linear_velocity, angular_velocity, velocity)| BodyState {
+                    entity,
+                    transform: *transform,
+                    linear_velocity: linear_velocity.copied(),
+                    angular_velocity: angular_velocity.copied(),
+                    velocity: velocity.copied(),
+                },
+            )
+            .collect();
+
+        Some(Self {
+            bodies,
+            score: world.resource::<Score>().clone(),
+            serve: world.resource::<Serve>().clone(),
+            rally: *world.resource::<Rally>(),
+            phase_timer: world.resource::<PhaseTimer>().clone(),
+            match_state: *world.get_resource::<State<MatchState>>()?.get(),
+            rng: world.resource::<GameRng>().clone(),
+            collisions: world.resource::<Collisions>().clone(),
+            difficulty: world.resource::<AdaptiveDifficulty>().clone(),
+        })
+    }
+
+    fn restore(&self, world: &mut World) {
+        // Change state the normal way so OnExit and OnEnter run, then overwrite whatever they
+        // touched with the snapshot
+        if *world.resource::<State<MatchState>>().get() != self.match_state {
+            world
+                .resource_mut::<NextState<MatchState>>()
+                .set(self.match_state);
+            world.run_schedule(StateTransition);
+        }
+
+        for body in &self.bodies {
+            let Some(mut entity) = world.get_entity_mut(body.entity) else {
+                continue;
+            };
+
+            entity.insert((body.transform, GlobalTransform::from(body.transform)));
+            if let Some(linear_velocity) = body.linear_velocity {
+                entity.insert(linear_velocity);
+            }
+            if let Some(angular_velocity) = body.angular_velocity {
+                entity.insert(angular_velocity);
+            }
+            if let Some(velocity) = body.velocity {
+                entity.insert(velocity);
+            }
+        }
+
+        world.insert_resource(self.score.clone());
+        world.insert_resource(self.serve.clone());
+        world.insert_resource(self.rally);
+        world.insert_resource(self.phase_timer.clone());
+        world.insert_resource(self.rng.clone());
+        world.insert_resource(self.collisions.clone());
+        world.insert_resource(self.difficulty.clone());
+    }
+
+    // Hash of the snapshot, compared with the peer's for the same tick to catch desyncs
+    fn checksum(&self, tick: u64) -> u64 {
+        let mut hash = Fnv1a::default();
+        hash.write(&tick.to_le_bytes());
+        hash.write(&self.score.player.to_le_bytes());
+        hash.write(&self.score.ai.to_le_bytes());
+        hash.write(&self.rally.hits.to_le_bytes());
+
+        // Entity ids can differ between the two games, so go by position in the field instead
+        let mut bodies: Vec<&BodyState> = self.bodies.iter().collect();
+        bodies.sort_by(|a, b| {
+            (a.velocity.is_some(), a.transform.translation.x)
+                .partial_cmp(&(b.velocity.is_some(), b.transform.translation.x))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        for body in bodies {
+            hash.write_vec2(body.transform.translation.truncate());
+            if let Some(linear_velocity) = body.linear_velocity {
+                hash.write_vec2(linear_velocity.0);
+            }
+        }
+
+        hash.0
+    }
+}
+
+// ##############################################################
+// # Systems
+// ##############################################################
+
+// Hold on to what the local player did this frame until the next tick uses it
+// Either side's keys work, since there's only one local player
+fn capture_local_input(tick_input: Res<TickInput>, mut session: ResMut<NetSession>) {
+    let movement = if tick_input.right.movement != 0. {
+        tick_input.right.movement
+    } else {
+        tick_input.left.movement
+    };
+
+    session.pending_local.movement = movement;
+    session.pending_local.serve |= tick_input.left.serve || tick_input.right.serve;
+}
+
+// Runs at the start of every tick, including resimulated ones: snapshot the state and decide
+// the inputs both paddles get
+fn net_tick(world: &mut World) {
+    let tick = world.resource::<Tick>().0;
+
+    world.resource_scope(|world, mut session: Mut<NetSession>| {
+        if session.status == NetStatus::Connecting {
+            return;
+        }
+
+        if !session.resimulating {
+            let delayed = tick + session.config.input_delay;
+            let input = std::mem::take(&mut session.pending_local);
+            session.local_inputs.insert(delayed, input);
+        }
+
+        if let Some(snapshot) = Snapshot::take(world) {
+            let checksum = snapshot.checksum(tick);
+            session.checksums.insert(tick, checksum);
+            session.snapshots.insert(tick, snapshot);
+        }
+
+        let local = session.local_inputs.get(&tick).copied().unwrap_or_default();
+        let remote = session.remote_input(tick);
+        session.predicted.insert(tick, remote);
+
+        let local_side = session.local_side;
+        let mut tick_input = world.resource_mut::<TickInput>();
+        *tick_input.side_mut(local_side) = local;
+        *tick_input.side_mut(other_side(local_side)) = remote;
+    });
+}
+
+// Once a frame: read everything the peer sent, roll back and replay if we guessed their
+// inputs wrong, send our inputs, and hold the simulation if we're too far ahead of them
+fn exchange_inputs(world: &mut World) {
+    let rollback_to = world
+        .resource_scope(|world, mut session: Mut<NetSession>| receive_packets(world, &mut session));
+
+    // Nothing else would ever unstall the match if the peer is gone
+    let session = world.resource::<NetSession>();
+    if session.timed_out() {
+        if session.status == NetStatus::Connecting {
+            eprintln!("The host didn't answer");
+            world.remove_resource::<NetSession>();
+        } else {
+            eprintln!("Lost connection to the peer, ending the match");
+            world
+                .resource_mut::<NextState<AppState>>()
+                .set(AppState::MainMenu);
+        }
+        return;
+    }
+
+    if let Some(from) = rollback_to {
+        rollback(world, from);
+    }
+
+    world.resource_scope(|world, mut session: Mut<NetSession>| {
+        send_inputs(world, &mut session);
+
+        let tick = world.resource::<Tick>().0;
+        let stalled = session.status != NetStatus::Connecting
+            && tick >= session.remote_confirmed + MAX_PREDICTION;
+
+        let mut time = world.resource_mut::<Time<Virtual>>();
+        time.set_relative_speed(if stalled { 0. } else { 1. });
+    });
+}
+
+// Returns the earliest tick that was simulated with a wrong guess of the peer's input
+fn receive_packets(world: &mut World, session: &mut NetSession) -> Option<u64> {
+    let tick = world.resource::<Tick>().0;
+    let mut rollback_to: Option<u64> = None;
+    let mut buffer = [0; 2048];
+
+    loop {
+        let (len, from) = match session.socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) => {
+                eprintln!("Network error: {err}");
+                break;
+            }
+        };
+
+        if session.peer.is_some_and(|peer| peer != from) {
+            continue;
+        }
+        let Some(packet) = Packet::from_bytes(&buffer[..len]) else {
+            continue;
+        };
+        session.last_heard = Instant::now();
+
+        match packet {
+            Packet::Hello if session.local_side == Side::Left => {
+                session.peer = Some(from);
+                let rules = world.resource::<GameRules>().clone();
+                let seed = if session.status == NetStatus::Connecting {
+                    rand::random()
+                } else {
+                    world.resource::<GameRng>().seed
+                };
+                session.send(&Packet::Welcome {
+                    seed,
+                    rules: rules.clone(),
+                });
+
+                if session.status == NetStatus::Connecting {
+                    println!("{from} joined");
+                    start_match(world, session, seed, rules);
+                }
+            }
+            Packet::Welcome { seed, rules } if session.status == NetStatus::Connecting => {
+                println!("Joined, starting match");
+                start_match(world, session, seed, rules);
+            }
+            Packet::Inputs {
+                ack,
+                first_tick,
+                inputs,
+                checksums,
+            } if session.status != NetStatus::Connecting => {
+                session.peer_ack = session.peer_ack.max(ack);
+
+                for (offset, input) in inputs.into_iter().enumerate() {
+                    let input_tick = first_tick + offset as u64;
+                    if input_tick < session.remote_confirmed {
+                        continue;
+                    }
+                    session.remote_inputs.insert(input_tick, input);
+
+                    // Already simulated with a guess, which was wrong
+                    if input_tick < tick && session.predicted.get(&input_tick) != Some(&input) {
+                        rollback_to = Some(rollback_to.map_or(input_tick, |t| t.min(input_tick)));
+                    }
+                }
+                while session
+                    .remote_inputs
+                    .contains_key(&session.remote_confirmed)
+                {
+                    session.remote_confirmed += 1;
+                }
+
+                session.remote_checksums.extend(checksums);
+            }
+            _ => (),
+        }
+    }
+
+    rollback_to
+}
+
+fn send_inputs(world: &mut World, session: &mut NetSession) {
+    let tick = world.resource::<Tick>().0;
+
+    match session.status {
+        NetStatus::Connecting => {
+            if session.local_side == Side::Right {
+                session.send(&Packet::Hello);
+            }
+        }
+        NetStatus::Running | NetStatus::Desynced(_) => {
+            // Older inputs may be pruned already, if the peer has been gone a while
+            let first_tick = session
+                .local_inputs
+                .range(session.peer_ack..)
+                .next()
+                .map_or(session.peer_ack, |(first, _)| *first);
+            let inputs: Vec<SideInput> = session
+                .local_inputs
+                .range(first_tick..)
+                .map(|(_, input)| *input)
+                .take(MAX_INPUTS_PER_PACKET)
+                .collect();
+
+            // Only checksums of ticks both sides have all the inputs for mean anything
+            let checksums: Vec<(u64, u64)> = session
+                .checksums
+                .range(..=session.remote_confirmed.min(tick))
+                .filter(|(checksum_tick, _)| *checksum_tick % CHECKSUM_INTERVAL == 0)
+                .rev()
+                .take(4)
+                .map(|(checksum_tick, checksum)| (*checksum_tick, *checksum))
+                .collect();
+
+            check_desync(session, tick);
+
+            session.send(&Packet::Inputs {
+                ack: session.remote_confirmed,
+                first_tick,
+                inputs,
+                checksums,
+            });
+            session.prune(tick.min(session.remote_confirmed));
+        }
+    }
+
+    let (socket, link) = (&session.socket, &mut session.link);
+    link.flush(socket);
+}
+
+fn check_desync(session: &mut NetSession, tick: u64) {
+    if session.status != NetStatus::Running {
+        return;
+    }
+
+    let confirmed = session.remote_confirmed.min(tick);
+    for (checksum_tick, remote) in &session.remote_checksums {
+        if *checksum_tick > confirmed {
+            break;
+        }
+        if session
+            .checksums
+            .get(checksum_tick)
+            .is_some_and(|local| local != remote)
+        {
+            eprintln!("Desync detected at tick {checksum_tick}");
+            session.status = NetStatus::Desynced(*checksum_tick);
+            return;
+        }
+    }
+}
+
+// Go back to the snapshot at from and replay every tick since, now with the real inputs
+fn rollback(world: &mut World, from: u64) {
+    let current = world.resource::<Tick>().0;
+
+    let restored = world.resource_scope(|world, session: Mut<NetSession>| {
+        let Some(snapshot) = session.snapshots.get(&from) else {
+            return false;
+        };
+        snapshot.restore(world);
+        world.resource_mut::<Tick>().0 = from;
+        true
+    });
+
+    if !restored {
+        eprintln!("Can't roll back to tick {from}, it's too old");
+        world.resource_mut::<NetSession>().status = NetStatus::Desynced(from);
+        return;
+    }
+
+    world.resource_mut::<NetSession>().resimulating = true;
+    while world.resource::<Tick>().0 < current {
+        run_fixed_tick(world);
+
+        // Gameplay has read this tick's events by now. Anything reading them outside the tick
+        // already saw them the first time around, so don't let them through again
+        world.resource_mut::<Events<ScoreEvent>>().clear();
+        world.resource_mut::<Events<PaddleHit>>().clear();
+        world.resource_mut::<Events<MatchWon>>().clear();
+    }
+    world.resource_mut::<NetSession>().resimulating = false;
+}
+
+// For systems in FixedUpdate that shouldn't run again for ticks replayed by a rollback
+pub fn resimulating(session: Option<Res<NetSession>>) -> bool {
+    session.is_some_and(|session| session.resimulating)
+}
+
+fn start_match(world: &mut World, session: &mut NetSession, seed: u64, rules: GameRules) {
+    session.start();
+    world.insert_resource(rules);
+    world.insert_resource(GameRng::new(seed));
+    world.insert_resource(GameMode::Online);
+    world
+        .resource_mut::<NextState<AppState>>()
+        .set(AppState::InGame);
+}
+
+fn end_session(mut commands: Commands, mut time: ResMut<Time<Virtual>>) {
+    commands.remove_resource::<NetSession>();
+    time.set_relative_speed(1.);
+}
+
+// Backing out of the LAN games screen gives up on a join the host hasn't answered yet
+fn cancel_join(mut commands: Commands, session: Option<Res<NetSession>>) {
+    if session.is_some_and(|session| {
+        session.status == NetStatus::Connecting && session.local_side == Side::Right
+    }) {
+        commands.remove_resource::<NetSession>();
+    }
+}
+
+// Starting a match here gives up on hosting or joining one online. Sessions that connected
+// have already moved on from Connecting
+fn cancel_connecting(mut commands: Commands, session: Option<Res<NetSession>>) {
+    if session.is_some_and(|session| session.status == NetStatus::Connecting) {
+        println!("Playing here instead, closing the online match");
+        commands.remove_resource::<NetSession>();
+    }
+}
+
+fn other_side(side: Side) -> Side {
+    match side {
+        Side::Left => Side::Right,
+        Side::Right => Side::Left,
+    }
+}
+
+// ##############################################################
+// # Packets
+// ##############################################################
+
+enum Packet {
+    Hello,
+    Welcome {
+        seed: u64,
+        rules: GameRules,
+    },
+    Inputs {
+        ack: u64,        // Sender has every input before this tick
+        first_tick: u64, // Tick of the first input
+        inputs: Vec<SideInput>,
+        checksums: Vec<(u64, u64)>, // Tick and checksum pairs
+    },
+}
+
+impl Packet {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Packet::Hello => bytes.push(0),
+            Packet::Welcome { seed, rules } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&seed.to_le_bytes());
+                bytes.extend_from_slice(ron::to_string(rules).unwrap_or_default().as_bytes());
+            }
+            Packet::Inputs {
+                ack,
+                first_tick,
+                inputs,
+                checksums,
+            } => {
+                bytes.push(2);
+                bytes.extend_from_slice(&ack.to_le_bytes());
+                bytes.extend_from_slice(&first_tick.to_le_bytes());
+                bytes.push(inputs.len() as u8);
+                for input in inputs {
+                    bytes.extend_from_slice(&input.movement.to_le_bytes());
+                    bytes.push(input.serve as u8);
+                }
+                bytes.push(checksums.len() as u8);
+                for (tick, checksum) in checksums {
+                    bytes.extend_from_slice(&tick.to_le_bytes());
+                    bytes.extend_from_slice(&checksum.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    // None for anything malformed, which is dropped like a lost packet
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (kind, mut rest) = bytes.split_first()?;
+        match kind {
+            0 => Some(Packet::Hello),
+            1 => {
+                let seed = u64::from_le_bytes(take(&mut rest)?);
+                let rules = GameRules::parse(std::str::from_utf8(rest).ok()?).ok()?;
+                Some(Packet::Welcome { seed, rules })
+            }
+            2 => {
+                let ack = u64::from_le_bytes(take(&mut rest)?);
+                let first_tick = u64::from_le_bytes(take(&mut rest)?);
+                let [input_count] = take(&mut rest)?;
+                let inputs = (0..input_count)
+                    .map(|_| {
+                        // Cleaned up like the server does, so a bad peer can't send the
+                        // paddle off the field. Real inputs are already in range
+                        let movement = clamp_movement(f32::from_le_bytes(take(&mut rest)?));
+                        let [serve] = take(&mut rest)?;
+                        Some(SideInput {
+                            movement,
+                            serve: serve != 0,
+                        })
+                    })
+                    .collect::<Option<_>>()?;
+                let [checksum_count] = take(&mut rest)?;
+                let checksums = (0..checksum_count)
+                    .map(|_| {
+                        Some((
+                            u64::from_le_bytes(take(&mut rest)?),
+                            u64::from_le_bytes(take(&mut rest)?),
+                        ))
+                    })
+                    .collect::<Option<_>>()?;
+                Some(Packet::Inputs {
+                    ack,
+                    first_tick,
+                    inputs,
+                    checksums,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+    let (head, rest) = bytes.split_first_chunk::<N>()?;
+    *bytes = rest;
+    Some(*head)
+}
+
+// ##############################################################
+// # Link Conditioner
+// ##############################################################
+
+// Delays and drops outgoing packets to test bad connections over loopback
+struct LinkConditioner {
+    latency: Duration,
+    jitter: Duration,
+    loss: f32,
+    queue: Vec<(Instant, SocketAddr, Vec<u8>)>,
+    rng: StdRng,
+}
+
+impl LinkConditioner {
+    fn new(config: &NetConfig) -> Self {
+        Self {
+            latency: config.latency,
+            jitter: config.jitter,
+            loss: config.loss,
+            queue: Vec::new(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    fn send(&mut self, socket: &UdpSocket, addr: SocketAddr, bytes: Vec<u8>) {
+        if self.loss > 0. && self.rng.gen::<f32>() < self.loss {
+            return;
+        }
+
+        if self.latency.is_zero() && self.jitter.is_zero() {
+            let _ = socket.send_to(&bytes, addr);
+        } else {
+            let jitter = self.jitter.mul_f32(self.rng.gen());
+            self.queue
+                .push((Instant::now() + self.latency + jitter, addr, bytes));
+        }
+    }
+
+    // Send every delayed packet that's due
+    fn flush(&mut self, socket: &UdpSocket) {
+        let now = Instant::now();
+        self.queue.retain(|(due, addr, bytes)| {
+            if *due <= now {
+                let _ = socket.send_to(bytes, *addr);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
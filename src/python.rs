@@ -0,0 +1,219 @@
+// The code pymethods generates for methods returning PyResult trips this lint
+#![allow(clippy::useless_conversion)]
+
+use numpy::{
+    ndarray::{Array2, Array3, Array4},
+    IntoPyArray, PyArray1, PyArray2, PyArray3, PyArray4, PyReadonlyArray2,
+};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{
+    env::{Observation, PongEnv},
+    rules::GameRules,
+    structure::GameMode,
+};
+
+// Python module, built with the python feature. Gives training code headless matches to step
+// through, one at a time or many at once, without a window or GPU
+
+// One match. Mirrors PongEnv, with observations as float32 arrays of Observation::SIZE
+#[pyclass(name = "Env", unsendable)]
+struct PyEnv {
+    env: PongEnv,
+}
+
+#[pymethods]
+impl PyEnv {
+    #[new]
+    #[pyo3(signature = (mode = "vs_ai", rules = None, max_ticks = None))]
+    fn new(mode: &str, rules: Option<&str>, max_ticks: Option<u32>) -> PyResult<Self> {
+        Ok(Self {
+            env: new_env(mode, rules, max_ticks)?,
+        })
+    }
+
+    fn reset<'py>(&mut self, py: Python<'py>, seed: u64) -> Bound<'py, PyArray1<f32>> {
+        PyArray1::from_slice_bound(py, &self.env.reset(seed).to_array())
+    }
+
+    fn step<'py>(
+        &mut self,
+        py: Python<'py>,
+        left: f32,
+        right: f32,
+    ) -> (Bound<'py, PyArray1<f32>>, f32, bool) {
+        let (observation, reward, done) = self.env.step(left, right);
+        (
+            PyArray1::from_slice_bound(py, &observation.to_array()),
+            reward,
+            done,
+        )
+    }
+
+    // height x width x 3 uint8 image of the field
+    #[pyo3(signature = (width = 160, height = 120))]
+    fn render<'py>(
+        &mut self,
+        py: Python<'py>,
+        width: usize,
+        height: usize,
+    ) -> Bound<'py, PyArray3<u8>> {
+        Array3::from_shape_vec((height, width, 3), self.env.render(width, height))
+            .expect("render fills the whole image")
+            .into_pyarray_bound(py)
+    }
+
+    // Points for the left and right paddle
+    #[getter]
+    fn score(&self) -> (u32, u32) {
+        let score = self.env.score();
+        (score.ai, score.player)
+    }
+}
+
+// Several matches stepped together, with observations, rewards and done flags stacked along
+// the first axis. Finished matches restart straight away with the next seed, so the
+// observation returned with done set is the first of the new match, like gym's vector envs
+#[pyclass(name = "VecEnv", unsendable)]
+struct PyVecEnv {
+    envs: Vec<PongEnv>,
+    next_seed: u64,
+}
+
+#[pymethods]
+impl PyVecEnv {
+    #[new]
+    #[pyo3(signature = (count, mode = "vs_ai", rules = None, max_ticks = None))]
+    fn new(
+        count: usize,
+        mode: &str,
+        rules: Option<&str>,
+        max_ticks: Option<u32>,
+    ) -> PyResult<Self> {
+        let envs = (0..count)
+            .map(|_| new_env(mode, rules, max_ticks))
+            .collect::<PyResult<_>>()?;
+        Ok(Self { envs, next_seed: 0 })
+    }
+
+    fn __len__(&self) -> usize {
+        self.envs.len()
+    }
+
+    // Match i gets seed + i
+    fn reset<'py>(&mut self, py: Python<'py>, seed: u64) -> Bound<'py, PyArray2<f32>> {
+        let observations: Vec<Observation> = self
+            .envs
+            .iter_mut()
+            .enumerate()
+            .map(|(index, env)| env.reset(seed + index as u64))
+            .collect();
+        self.next_seed = seed + self.envs.len() as u64;
+        stack(py, &observations)
+    }
+
+    // Actions are a count x 2 array of left and right paddle velocities
+    #[allow(clippy::type_complexity)]
+    fn step<'py>(
+        &mut self,
+        py: Python<'py>,
+        actions: PyReadonlyArray2<'py, f32>,
+    ) -> PyResult<(
+        Bound<'py, PyArray2<f32>>,
+        Bound<'py, PyArray1<f32>>,
+        Bound<'py, PyArray1<bool>>,
+    )> {
+        let actions = actions.as_array();
+        if actions.shape() != [self.envs.len(), 2] {
+            return Err(PyValueError::new_err(format!(
+                "expected actions of shape ({}, 2), got {:?}",
+                self.envs.len(),
+                actions.shape()
+            )));
+        }
+
+        let mut observations = Vec::with_capacity(self.envs.len());
+        let mut rewards = Vec::with_capacity(self.envs.len());
+        let mut dones = Vec::with_capacity(self.envs.len());
+
+        for (index, env) in self.envs.iter_mut().enumerate() {
+            let (mut observation, reward, done) =
+                env.step(actions[[index, 0]], actions[[index, 1]]);
+            if done {
+                observation = env.reset(self.next_seed);
+                self.next_seed += 1;
+            }
+            observations.push(observation);
+            rewards.push(reward);
+            dones.push(done);
+        }
+
+        Ok((
+            stack(py, &observations),
+            PyArray1::from_vec_bound(py, rewards),
+            PyArray1::from_vec_bound(py, dones),
+        ))
+    }
+
+    // count x height x width x 3 uint8 images
+    #[pyo3(signature = (width = 160, height = 120))]
+    fn render<'py>(
+        &mut self,
+        py: Python<'py>,
+        width: usize,
+        height: usize,
+    ) -> Bound<'py, PyArray4<u8>> {
+        let pixels: Vec<u8> = self
+            .envs
+            .iter_mut()
+            .flat_map(|env| env.render(width, height))
+            .collect();
+        Array4::from_shape_vec((self.envs.len(), height, width, 3), pixels)
+            .expect("render fills the whole image")
+            .into_pyarray_bound(py)
+    }
+}
+
+fn new_env(mode: &str, rules: Option<&str>, max_ticks: Option<u32>) -> PyResult<PongEnv> {
+    let mode = match mode {
+        "vs_ai" => GameMode::VsAi,
+        "versus" => GameMode::LocalVersus,
+        "ai_vs_ai" => GameMode::AiVersusAi,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "unknown mode {mode:?}, expected vs_ai, versus or ai_vs_ai"
+            )))
+        }
+    };
+
+    // Rules are given as the contents of a rules file
+    let rules = match rules {
+        Some(source) => {
+            GameRules::parse(source).map_err(|err| PyValueError::new_err(err.to_string()))?
+        }
+        None => GameRules::default(),
+    };
+
+    let mut env = PongEnv::new(rules, mode);
+    if let Some(max_ticks) = max_ticks {
+        env.max_ticks = max_ticks;
+    }
+    Ok(env)
+}
+
+fn stack<'py>(py: Python<'py>, observations: &[Observation]) -> Bound<'py, PyArray2<f32>> {
+    let values = observations
+        .iter()
+        .flat_map(|observation| observation.to_array())
+        .collect();
+    Array2::from_shape_vec((observations.len(), Observation::SIZE), values)
+        .expect("every observation is the same size")
+        .into_pyarray_bound(py)
+}
+
+#[pymodule]
+fn bevy_pong(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyEnv>()?;
+    module.add_class::<PyVecEnv>()?;
+    Ok(())
+}
@@ -0,0 +1,593 @@
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+use avian2d::prelude::LinearVelocity;
+use bevy::{
+    app::{App, AppExit, FixedUpdate, Last, Update},
+    ecs::system::SystemParam,
+    input::ButtonInput,
+    math::Vec2,
+    prelude::{
+        default, in_state, not, resource_exists, Color, Commands, Component, EventReader,
+        IntoSystemConfigs, KeyCode, NextState, OnEnter, OnExit, Plugin, Query, Res, ResMut,
+        Resource, State, StateScoped, Text, TextBundle, TextStyle, With, World,
+    },
+    state::state::StateTransition,
+    time::{Time, Virtual},
+    ui::{PositionType, Style, Val},
+};
+
+use crate::{gamestate::run_fixed_tick, rules::GameRules, structure::*};
+
+// Where the last match played is saved, relative to the working directory
+pub const REPLAY_PATH: &str = "last_match.replay";
+
+// Identifies replay files, bumped whenever the format changes
+const MAGIC: &[u8; 8] = b"PONGRPL1";
+
+// Bytes each tick's inputs take up in a replay file
+const FRAME_SIZE: usize = 9;
+
+// Ticks between state checksums
+const CHECKSUM_INTERVAL: u32 = 64;
+
+// How far the arrow keys seek, five seconds at the default timestep
+const SEEK_TICKS: u64 = 320;
+
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 8.;
+
+pub struct PongReplayPlugin;
+
+impl Plugin for PongReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Recorder>();
+        app.add_systems(
+            OnEnter(AppState::InGame),
+            (
+                start_recording,
+                spawn_playback_hud.run_if(resource_exists::<Playback>),
+            ),
+        );
+        app.add_systems(
+            FixedUpdate,
+            (
+                play_inputs
+                    .in_set(GameplaySet::Input)
+                    .run_if(resource_exists::<Playback>),
+                (
+                    record_inputs.run_if(not(resource_exists::<Playback>)),
+                    verify_checksums.run_if(resource_exists::<Playback>),
+                )
+                    .after(GameplaySet::Input)
+                    .before(GameplaySet::Movement),
+            )
+                .run_if(in_state(AppState::InGame)),
+        );
+        app.add_systems(
+            Update,
+            (playback_controls, seek_replay, update_playback_hud)
+                .chain()
+                .run_if(resource_exists::<Playback>)
+                .run_if(in_state(AppState::InGame)),
+        );
+        app.add_systems(OnEnter(MatchState::GameOver), save_recording);
+        app.add_systems(OnExit(AppState::InGame), (save_recording, stop_playback));
+        app.add_systems(
+            Last,
+            save_recording_on_exit.run_if(in_state(AppState::InGame)),
+        );
+    }
+}
+
+// ##############################################################
+// # Replay Files
+// ##############################################################
+
+// Everything needed to play a match back exactly: the rules and seed it started with, and
+// what each paddle did every tick. Frames are indexed by Tick, and for AI paddles the
+// movement is what the AI decided, so replays don't depend on the AI code staying the same
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub mode: GameMode,
+    pub rules: GameRules,
+    pub checksum_interval: u32,
+    pub frames: Vec<TickInput>,
+    pub checksums: Vec<u64>, // State checksum every checksum_interval ticks, starting at tick 0
+}
+
+impl Replay {
+    pub fn new(seed: u64, mode: GameMode, rules: GameRules) -> Self {
+        Self {
+            seed,
+            mode,
+            rules,
+            checksum_interval: CHECKSUM_INTERVAL,
+            frames: Vec::new(),
+            checksums: Vec::new(),
+        }
+    }
+
+    // The recorded checksum for a tick, if one was taken on it
+    pub fn checksum_at(&self, tick: u64) -> Option<u64> {
+        if !tick.is_multiple_of(self.checksum_interval as u64) {
+            return None;
+        }
+        self.checksums
+            .get((tick / self.checksum_interval as u64) as usize)
+            .copied()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), io::Error> {
+        fs::write(path, self.to_bytes()?)
+    }
+
+    pub fn load(path: &str) -> Result<Self, io::Error> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    // Little endian binary, with the rules embedded as RON so they stay readable:
+    // magic, seed, mode, checksum interval, rules, frames, checksums
+    // Each frame is both paddles' movement as f32s and the serve presses packed into a byte
+    pub fn to_bytes(&self) -> Result<Vec<u8>, io::Error> {
+        let rules = ron::to_string(&self.rules)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let mut bytes = Vec::with_capacity(32 + rules.len() + self.frames.len() * FRAME_SIZE);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.push(mode_to_byte(self.mode));
+        bytes.extend_from_slice(&self.checksum_interval.to_le_bytes());
+        bytes.extend_from_slice(&(rules.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(rules.as_bytes());
+
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for frame in &self.frames {
+            bytes.extend_from_slice(&frame.left.movement.to_le_bytes());
+            bytes.extend_from_slice(&frame.right.movement.to_le_bytes());
+            bytes.push((frame.left.serve as u8) | ((frame.right.serve as u8) << 1));
+        }
+
+        bytes.extend_from_slice(&(self.checksums.len() as u32).to_le_bytes());
+        for checksum in &self.checksums {
+            bytes.extend_from_slice(&checksum.to_le_bytes());
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, io::Error> {
+        let reader = &mut bytes;
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data(
+                "not a replay file, or from an incompatible version",
+            ));
+        }
+
+        let seed = u64::from_le_bytes(read_array(reader)?);
+        let mode = mode_from_byte(read_array::<1>(reader)?[0])?;
+        let checksum_interval = u32::from_le_bytes(read_array(reader)?);
+        if checksum_interval == 0 {
+            return Err(invalid_data("checksum interval must be at least 1"));
+        }
+
+        let rules_len = read_len(reader, 1)?;
+        let mut rules = vec![0; rules_len];
+        reader.read_exact(&mut rules)?;
+        let rules = String::from_utf8(rules).map_err(invalid_data)?;
+        let rules = GameRules::parse(&rules).map_err(invalid_data)?;
+
+        let frame_count = read_len(reader, FRAME_SIZE)?;
+        let mut frames = Vec::with_capacity(frame_count);
+        for _ in 0..frame_count {
+            let left = f32::from_le_bytes(read_array(reader)?);
+            let right = f32::from_le_bytes(read_array(reader)?);
+            let serves = read_array::<1>(reader)?[0];
+            frames.push(TickInput {
+                left: SideInput {
+                    movement: left,
+                    serve: serves & 1 != 0,
+                },
+                right: SideInput {
+                    movement: right,
+                    serve: serves & 2 != 0,
+                },
+            });
+        }
+
+        let checksum_count = read_len(reader, 8)?;
+        let mut checksums = Vec::with_capacity(checksum_count);
+        for _ in 0..checksum_count {
+            checksums.push(u64::from_le_bytes(read_array(reader)?));
+        }
+
+        Ok(Self {
+            seed,
+            mode,
+            rules,
+            checksum_interval,
+            frames,
+            checksums,
+        })
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut &[u8]) -> Result<[u8; N], io::Error> {
+    let mut array = [0; N];
+    reader.read_exact(&mut array)?;
+    Ok(array)
+}
+
+// Read a count of items item_size bytes long, making sure that many are actually left before
+// anything gets allocated for them, so a corrupt file can't ask for gigabytes
+fn read_len(reader: &mut &[u8], item_size: usize) -> Result<usize, io::Error> {
+    let count = u32::from_le_bytes(read_array(reader)?) as usize;
+    if count.saturating_mul(item_size) > reader.len() {
+        return Err(invalid_data(format!(
+            "replay says it has {count} entries of {item_size} bytes, but only {} bytes are left",
+            reader.len()
+        )));
+    }
+    Ok(count)
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn mode_to_byte(mode: GameMode) -> u8 {
+    match mode {
+        GameMode::VsAi => 0,
+        GameMode::LocalVersus => 1,
+        GameMode::AiVersusAi => 2,
+        GameMode::Online => 3,
+    }
+}
+
+fn mode_from_byte(byte: u8) -> Result<GameMode, io::Error> {
+    match byte {
+        0 => Ok(GameMode::VsAi),
+        1 => Ok(GameMode::LocalVersus),
+        2 => Ok(GameMode::AiVersusAi),
+        3 => Ok(GameMode::Online),
+        _ => Err(invalid_data(format!("unknown game mode {byte}"))),
+    }
+}
+
+// ##############################################################
+// # Checksums
+// ##############################################################
+
+// Hashes everything that has to match between two runs of the same match
+// Floats are hashed by their exact bits, so any divergence at all shows up
+#[derive(SystemParam)]
+pub struct StateChecksum<'w, 's> {
+    ball: Query<'w, 's, (&'static Position, &'static LinearVelocity), With<Ball>>,
+    paddles: Query<'w, 's, (&'static Position, &'static Side), With<Paddle>>,
+    score: Res<'w, Score>,
+    tick: Res<'w, Tick>,
+}
+
+impl StateChecksum<'_, '_> {
+    pub fn get(&self) -> u64 {
+        let mut hash = Fnv1a::default();
+        hash.write(&self.tick.0.to_le_bytes());
+        hash.write(&self.score.player.to_le_bytes());
+        hash.write(&self.score.ai.to_le_bytes());
+
+        for (position, velocity) in &self.ball {
+            hash.write_vec2(position.0);
+            hash.write_vec2(velocity.0);
+        }
+
+        // Query order isn't guaranteed to be the same between apps, so go by side
+        for side in [Side::Left, Side::Right] {
+            for (position, paddle_side) in &self.paddles {
+                if *paddle_side == side {
+                    hash.write_vec2(position.0);
+                }
+            }
+        }
+
+        hash.0
+    }
+}
+
+// Small, stable hash. The std hashers are randomly seeded or may change between releases
+pub struct Fnv1a(pub u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv1a {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn write_vec2(&mut self, value: Vec2) {
+        self.write(&value.x.to_bits().to_le_bytes());
+        self.write(&value.y.to_bits().to_le_bytes());
+    }
+}
+
+// ##############################################################
+// # Recording
+// ##############################################################
+
+// The match currently being played, saved to REPLAY_PATH when it ends
+#[derive(Resource, Default)]
+pub struct Recorder(pub Option<Replay>);
+
+fn start_recording(
+    mut recorder: ResMut<Recorder>,
+    rng: Res<GameRng>,
+    mode: Res<GameMode>,
+    rules: Res<GameRules>,
+) {
+    recorder.0 = Some(Replay::new(rng.seed, *mode, rules.clone()));
+}
+
+// Store what each paddle was told to do this tick, after players and AI have decided
+fn record_inputs(
+    mut recorder: ResMut<Recorder>,
+    tick_input: Res<TickInput>,
+    paddles: Query<(&Velocity, &Side), With<Paddle>>,
+    checksum: StateChecksum,
+) {
+    let Some(replay) = &mut recorder.0 else {
+        return;
+    };
+
+    let mut frame = *tick_input;
+    for (velocity, side) in &paddles {
+        frame.side_mut(*side).movement = velocity.0.y;
+    }
+
+    // Rollback netcode replays ticks, so overwrite anything already recorded from this one on
+    let tick = checksum.tick.0;
+    let interval = replay.checksum_interval as u64;
+    replay.frames.truncate(tick as usize);
+    replay.checksums.truncate(tick.div_ceil(interval) as usize);
+
+    if tick.is_multiple_of(interval) {
+        replay.checksums.push(checksum.get());
+    }
+    replay.frames.push(frame);
+}
+
+fn save_recording(recorder: Res<Recorder>) {
+    if let Some(replay) = &recorder.0 {
+        if replay.frames.is_empty() {
+            return;
+        }
+        if let Err(err) = replay.save(REPLAY_PATH) {
+            eprintln!("Couldn't save replay to {REPLAY_PATH}: {err}");
+        }
+    }
+}
+
+// Closing the window skips OnExit, so save on the way out too
+fn save_recording_on_exit(recorder: Res<Recorder>, mut exits: EventReader<AppExit>) {
+    if exits.read().count() > 0 {
+        save_recording(recorder);
+    }
+}
+
+// ##############################################################
+// # Playback
+// ##############################################################
+
+// Present while a replay is being played back, feeding its frames in instead of players and AI
+#[derive(Resource)]
+pub struct Playback {
+    pub replay: Replay,
+    pub speed: f32,
+    pub seek_to: Option<u64>,
+    pub diverged_at: Option<u64>, // First tick whose checksum didn't match the recording
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            speed: 1.,
+            seek_to: None,
+            diverged_at: None,
+        }
+    }
+
+    // Tick the recording runs out on
+    pub fn end_tick(&self) -> u64 {
+        self.replay.frames.len() as u64
+    }
+}
+
+#[derive(Component)]
+struct PlaybackHud;
+
+fn play_inputs(
+    playback: Res<Playback>,
+    tick: Res<Tick>,
+    mut tick_input: ResMut<TickInput>,
+    mut paddles: Query<(&mut Velocity, &Side), With<Paddle>>,
+    mut pause_state: ResMut<NextState<PauseState>>,
+) {
+    // Past the end of the recording everything stands still
+    let frame = match playback.replay.frames.get(tick.0 as usize) {
+        Some(frame) => *frame,
+        None => {
+            pause_state.set(PauseState::Paused);
+            TickInput::default()
+        }
+    };
+
+    *tick_input = frame;
+    for (mut velocity, side) in &mut paddles {
+        velocity.0.y = frame.side(*side).movement;
+    }
+}
+
+fn verify_checksums(mut playback: ResMut<Playback>, checksum: StateChecksum) {
+    if playback.diverged_at.is_some() {
+        return;
+    }
+
+    let tick = checksum.tick.0;
+    if let Some(expected) = playback.replay.checksum_at(tick) {
+        if checksum.get() != expected {
+            eprintln!("Replay diverged from the recording at tick {tick}");
+            playback.diverged_at = Some(tick);
+        }
+    }
+}
+
+// Space pauses, up and down change speed, left and right seek, Home restarts
+// Pausing goes through PauseState like the pause key does, so the two can't disagree
+fn playback_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut playback: ResMut<Playback>,
+    mut time: ResMut<Time<Virtual>>,
+    pause_state: Res<State<PauseState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+    tick: Res<Tick>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        next_pause_state.set(match pause_state.get() {
+            PauseState::Running => PauseState::Paused,
+            PauseState::Paused => PauseState::Running,
+        });
+    }
+
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        playback.speed = (playback.speed * 2.).min(MAX_SPEED);
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        playback.speed = (playback.speed / 2.).max(MIN_SPEED);
+    }
+    time.set_relative_speed(playback.speed);
+
+    if keyboard_input.just_pressed(KeyCode::ArrowRight) {
+        playback.seek_to = Some(tick.0 + SEEK_TICKS);
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowLeft) {
+        playback.seek_to = Some(tick.0.saturating_sub(SEEK_TICKS));
+    }
+    if keyboard_input.just_pressed(KeyCode::Home) {
+        playback.seek_to = Some(0);
+    }
+}
+
+// Ticks can't be undone, so seeking backwards restarts the match and both directions
+// fast forward to the target
+fn seek_replay(world: &mut World) {
+    let Some(target) = world.resource_mut::<Playback>().seek_to.take() else {
+        return;
+    };
+    let target = target.min(world.resource::<Playback>().end_tick());
+
+    if target < world.resource::<Tick>().0 {
+        restart_playback(world);
+    }
+
+    while world.resource::<Tick>().0 < target {
+        run_fixed_tick(world);
+    }
+}
+
+// Leave and re-enter the match so everything is spawned and reset the normal way, without
+// the Playback resource so leaving doesn't end playback
+fn restart_playback(world: &mut World) {
+    let pause_state = *world.resource::<State<PauseState>>().get();
+    let mut playback = world
+        .remove_resource::<Playback>()
+        .expect("only seeking during playback");
+    playback.diverged_at = None;
+
+    world
+        .resource_mut::<NextState<AppState>>()
+        .set(AppState::MainMenu);
+    world.run_schedule(StateTransition);
+
+    world.insert_resource(GameRng::new(playback.replay.seed));
+    world.insert_resource(playback);
+    world
+        .resource_mut::<NextState<AppState>>()
+        .set(AppState::InGame);
+    world.run_schedule(StateTransition);
+
+    world
+        .resource_mut::<NextState<PauseState>>()
+        .set(pause_state);
+    world.run_schedule(StateTransition);
+}
+
+// Back to normal play once the replay is left through the pause menu, at normal speed too
+fn stop_playback(
+    mut commands: Commands,
+    playback: Option<Res<Playback>>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if playback.is_some() {
+        commands.remove_resource::<Playback>();
+        time.set_relative_speed(1.);
+    }
+}
+
+fn spawn_playback_hud(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 20.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.),
+            bottom: Val::Px(30.),
+            ..default()
+        }),
+        PlaybackHud,
+        StateScoped(AppState::InGame),
+    ));
+}
+
+fn update_playback_hud(
+    mut hud: Query<&mut Text, With<PlaybackHud>>,
+    playback: Res<Playback>,
+    tick: Res<Tick>,
+    pause_state: Res<State<PauseState>>,
+) {
+    if let Ok(mut text) = hud.get_single_mut() {
+        let status = match pause_state.get() {
+            PauseState::Running => "Replay",
+            PauseState::Paused => "Paused",
+        };
+        let mut value = format!(
+            "{status} {}x  {}/{}  (Space pause, Up/Down speed, Left/Right seek, Home restart)",
+            playback.speed,
+            tick.0,
+            playback.end_tick()
+        );
+        if let Some(diverged_at) = playback.diverged_at {
+            value.push_str(&format!(
+                "\nDiverged from the recording at tick {diverged_at}"
+            ));
+        }
+        text.sections[0].value = value;
+    }
+}
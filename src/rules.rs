@@ -0,0 +1,367 @@
+use std::{fmt, fs, io, path::Path};
+
+use bevy::{
+    app::{App, Startup, Update},
+    asset::{
+        io::Reader, Asset, AssetApp, AssetEvent, AssetLoader, AssetServer, Assets, AsyncReadExt,
+        Handle, LoadContext,
+    },
+    log::info,
+    prelude::{
+        not, resource_exists, Commands, EventReader, IntoSystemConfigs, Plugin, Res, ResMut,
+        Resource,
+    },
+    reflect::TypePath,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ai::AiPersonality, client::ServerConnection, headless::TICK, netcode::NetSession,
+    replay::Playback, spectate::Spectating,
+};
+
+// Rules asset path, relative to the assets folder
+pub const RULES_ASSET: &str = "game.rules.ron";
+
+pub struct PongRulesPlugin;
+
+impl Plugin for PongRulesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameRules>();
+        app.init_asset::<GameRules>();
+        app.register_asset_loader(RulesLoader);
+        app.add_systems(Startup, load_rules);
+        // Replays, online matches and spectated matches keep the rules they were started with
+        app.add_systems(
+            Update,
+            apply_rules
+                .run_if(not(resource_exists::<Playback>))
+                .run_if(not(resource_exists::<NetSession>))
+                .run_if(not(resource_exists::<ServerConnection>))
+                .run_if(not(resource_exists::<Spectating>)),
+        );
+    }
+}
+
+// ##############################################################
+// # Game Rules
+// ##############################################################
+
+// Every piece of gameplay tuning, read by systems instead of hardcoded constants
+// The resource is the copy in use, the asset of the same type is what's on disk
+// Fields missing from the rules file fall back to their defaults
+#[derive(Resource, Asset, TypePath, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameRules {
+    // Dimensions of the playing field (and window) in world units
+    pub field_width: f32,
+    pub field_height: f32,
+    // Size of the ball in world units
+    pub ball_size: f32,
+    // Speed of the ball in world units
+    pub ball_speed: f32,
+    // Speed per fixed tick of the paddles in world units
+    pub paddle_speed: f32,
+    // Fraction of a paddle's vertical speed the ball picks up when it hits the paddle's face,
+    // both as extra vertical speed and as spin
+    pub paddle_english: f32,
+    // How hard spin curves the ball's path, 0 turns curving off
+    pub magnus_curve: f32,
+    // Paddle returns in a rally before the ball speeds up a tier, 0 turns speeding up off
+    pub hits_per_speed_tier: u32,
+    // Extra ball speed each tier adds, as a fraction of ball_speed
+    pub speed_per_tier: f32,
+    // Highest tier a rally can reach
+    pub max_speed_tier: u32,
+    // Dimensions of the paddles in world units
+    pub paddle_width: f32,
+    pub paddle_height: f32,
+    // Distance from the paddles to the edge of the field
+    pub paddle_inset: f32,
+    // Adjusts how rapidly the AI's smoothing function responds
+    pub ai_skill: f32,
+    // Which AI controller the left paddle uses against a player
+    pub ai_personality: AiPersonality,
+    // Whether the AI gets easier or harder over a match to keep it close. Only the Tracker and
+    // HumanLike personalities change, the others always play the same
+    pub adaptive_difficulty: bool,
+    // Fraction of points adaptive difficulty tries to have the AI win
+    pub target_ai_win_rate: f32,
+    // Range adaptive difficulty stays in, 0 is easiest and 1 is hardest
+    pub min_difficulty: f32,
+    pub max_difficulty: f32,
+    // Height of the top and bottom gutters in world units
+    pub gutter_height: f32,
+    // Points needed to win a match
+    pub target_score: u32,
+    // Whether the winner needs to be ahead by two points
+    pub win_by_two: bool,
+    // Seconds to wait after a point and before a serve
+    pub serve_delay: f32,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            field_width: 1280.,
+            field_height: 720.,
+            ball_size: 10.,
+            ball_speed: 200.,
+            paddle_speed: 5.,
+            paddle_english: 0.2,
+            magnus_curve: 0.,
+            hits_per_speed_tier: 2,
+            speed_per_tier: 0.1,
+            max_speed_tier: 10,
+            paddle_width: 10.,
+            paddle_height: 50.,
+            paddle_inset: 50.,
+            ai_skill: 15.,
+            ai_personality: AiPersonality::Tracker,
+            adaptive_difficulty: false,
+            target_ai_win_rate: 0.5,
+            min_difficulty: 0.,
+            max_difficulty: 1.,
+            gutter_height: 20.,
+            target_score: 11,
+            win_by_two: true,
+            serve_delay: 1.,
+        }
+    }
+}
+
+impl GameRules {
+    // Read and validate the rules file outside of the asset server, falling back to the
+    // defaults if there isn't one. Used to size the window before the app exists
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RulesError> {
+        let source = match fs::read_to_string(Path::new("assets").join(path)) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(RulesError::Io(err)),
+        };
+
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, RulesError> {
+        let rules: Self = ron::from_str(source).map_err(RulesError::Parse)?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    // Reject rules that would make the game unplayable
+    pub fn validate(&self) -> Result<(), RulesError> {
+        let positive = [
+            ("field_width", self.field_width),
+            ("field_height", self.field_height),
+            ("ball_size", self.ball_size),
+            ("ball_speed", self.ball_speed),
+            ("paddle_speed", self.paddle_speed),
+            ("paddle_width", self.paddle_width),
+            ("paddle_height", self.paddle_height),
+            ("ai_skill", self.ai_skill),
+            ("gutter_height", self.gutter_height),
+        ];
+        for (name, value) in positive {
+            if !value.is_finite() || value <= 0. {
+                return Err(RulesError::Invalid(format!(
+                    "{name} must be a positive number, got {value}"
+                )));
+            }
+        }
+
+        if !self.serve_delay.is_finite() || self.serve_delay < 0. {
+            return Err(RulesError::Invalid(format!(
+                "serve_delay can't be negative, got {}",
+                self.serve_delay
+            )));
+        }
+        if !(0. ..=1.).contains(&self.paddle_english) {
+            return Err(RulesError::Invalid(format!(
+                "paddle_english must be between 0 and 1, got {}",
+                self.paddle_english
+            )));
+        }
+        if !self.magnus_curve.is_finite() || self.magnus_curve < 0. {
+            return Err(RulesError::Invalid(format!(
+                "magnus_curve can't be negative, got {}",
+                self.magnus_curve
+            )));
+        }
+        if !self.speed_per_tier.is_finite() || self.speed_per_tier < 0. {
+            return Err(RulesError::Invalid(format!(
+                "speed_per_tier can't be negative, got {}",
+                self.speed_per_tier
+            )));
+        }
+        if !(0. ..=1.).contains(&self.target_ai_win_rate) {
+            return Err(RulesError::Invalid(format!(
+                "target_ai_win_rate must be between 0 and 1, got {}",
+                self.target_ai_win_rate
+            )));
+        }
+        if !(0. ..=1.).contains(&self.min_difficulty)
+            || !(0. ..=1.).contains(&self.max_difficulty)
+            || self.min_difficulty > self.max_difficulty
+        {
+            return Err(RulesError::Invalid(format!(
+                "min_difficulty ({}) and max_difficulty ({}) must be between 0 and 1, in order",
+                self.min_difficulty, self.max_difficulty
+            )));
+        }
+        if self.target_score == 0 {
+            return Err(RulesError::Invalid(String::from(
+                "target_score must be at least 1",
+            )));
+        }
+        if self.paddle_height >= self.play_height() {
+            return Err(RulesError::Invalid(format!(
+                "paddle_height ({}) must be smaller than the space between the gutters ({})",
+                self.paddle_height,
+                self.play_height()
+            )));
+        }
+        if self.ball_size >= self.play_height() {
+            return Err(RulesError::Invalid(format!(
+                "ball_size ({}) must be smaller than the space between the gutters ({})",
+                self.ball_size,
+                self.play_height()
+            )));
+        }
+        if self.paddle_inset < 0. || self.paddle_inset + self.paddle_width >= self.field_width / 2.
+        {
+            return Err(RulesError::Invalid(format!(
+                "paddle_inset ({}) must put the paddles inside their half of the field",
+                self.paddle_inset
+            )));
+        }
+
+        // The ball has to be touching a paddle at the end of a tick to be hit by it, so it can't
+        // be allowed to cross a whole paddle and itself in one
+        let crossing = self.paddle_width + self.ball_size * 2.;
+        if self.top_ball_speed() * TICK.as_secs_f32() >= crossing {
+            return Err(RulesError::Invalid(format!(
+                "the ball can go {} units a tick at the top speed tier, fast enough to go \
+                 through a paddle. Make it under {crossing}",
+                self.top_ball_speed() * TICK.as_secs_f32()
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Fastest the ball can go: a return at the top speed tier, with a moving paddle's english
+    // on top. Returns never add up to more than twice the tier's speed across both axes
+    pub fn top_ball_speed(&self) -> f32 {
+        let top_tier = 1. + self.max_speed_tier as f32 * self.speed_per_tier;
+        let english = self.paddle_speed / TICK.as_secs_f32() * self.paddle_english;
+        (self.ball_speed * 2. + english) * top_tier
+    }
+
+    // Vertical space the ball and paddles can move around in
+    pub fn play_height(&self) -> f32 {
+        self.field_height - self.gutter_height * 2.
+    }
+
+    // Furthest a paddle's center can get from the middle of the field
+    pub fn paddle_max_y(&self) -> f32 {
+        self.field_height / 2. - self.gutter_height - self.paddle_height / 2.
+    }
+
+    // One line description for match listings
+    pub fn summary(&self) -> String {
+        let lead = if self.win_by_two { ", win by two" } else { "" };
+        format!(
+            "First to {}{lead}, ball speed {}, paddle size {}",
+            self.target_score, self.ball_speed, self.paddle_height
+        )
+    }
+}
+
+// ##############################################################
+// # Hot Reloading
+// ##############################################################
+
+// Handle to the rules asset, kept around so it stays loaded and watched
+#[derive(Resource)]
+pub struct RulesHandle(pub Handle<GameRules>);
+
+// Parses *.rules.ron files into GameRules, rejecting invalid ones so a bad edit
+// leaves the previous rules in place
+#[derive(Default)]
+pub struct RulesLoader;
+
+impl AssetLoader for RulesLoader {
+    type Asset = GameRules;
+    type Settings = ();
+    type Error = RulesError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut source = String::new();
+        reader
+            .read_to_string(&mut source)
+            .await
+            .map_err(RulesError::Io)?;
+
+        GameRules::parse(&source)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["rules.ron"]
+    }
+}
+
+fn load_rules(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(RulesHandle(asset_server.load(RULES_ASSET)));
+}
+
+// Copy the rules asset into the GameRules resource whenever it's (re)loaded
+fn apply_rules(
+    mut events: EventReader<AssetEvent<GameRules>>,
+    handle: Option<Res<RulesHandle>>,
+    assets: Res<Assets<GameRules>>,
+    mut rules: ResMut<GameRules>,
+) {
+    let Some(handle) = handle else {
+        return;
+    };
+
+    for event in events.read() {
+        if event.is_loaded_with_dependencies(&handle.0) || event.is_modified(&handle.0) {
+            if let Some(new_rules) = assets.get(&handle.0) {
+                if *rules != *new_rules {
+                    info!("Applying updated game rules");
+                    *rules = new_rules.clone();
+                }
+            }
+        }
+    }
+}
+
+// ##############################################################
+// # Errors
+// ##############################################################
+
+#[derive(Debug)]
+pub enum RulesError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Invalid(String),
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesError::Io(err) => write!(f, "couldn't read rules file: {err}"),
+            RulesError::Parse(err) => write!(f, "couldn't parse rules file: {err}"),
+            RulesError::Invalid(reason) => write!(f, "invalid rules: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for RulesError {}
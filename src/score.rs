@@ -1,11 +1,12 @@
-use crate::structure::*;
-use avian2d::prelude::LinearVelocity;
+use crate::{rules::GameRules, structure::*};
+use avian2d::prelude::{AngularVelocity, LinearVelocity};
 use bevy::{
-    app::{App, Update},
+    app::{App, FixedUpdate, Update},
     ecs::schedule::IntoSystemConfigs,
     math::{Vec2, Vec3},
     prelude::{
-        DetectChanges, EventReader, EventWriter, Plugin, Query, Res, ResMut, Text, Transform, With,
+        in_state, DetectChanges, EventReader, EventWriter, NextState, OnEnter, Plugin, Query, Res,
+        ResMut, Text, Transform, With,
     },
 };
 
@@ -14,26 +15,56 @@ pub struct PongScorePlugin;
 impl Plugin for PongScorePlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<Score>();
+        app.init_resource::<GameMode>();
+        app.init_resource::<Serve>();
+        app.init_resource::<Rally>();
         app.add_event::<ScoreEvent>();
+        app.add_event::<MatchWon>();
         app.add_systems(
-            Update,
+            FixedUpdate,
             (
-                detect_scoring,
+                detect_scoring.run_if(in_state(MatchState::Playing)),
                 update_score,
-                (update_scoreboard, reset_ball),
+                check_match_won,
+                reset_ball,
             )
-                .chain(),
+                .chain()
+                .in_set(GameplaySet::Scoring),
+        );
+        app.add_systems(
+            Update,
+            (
+                (update_scoreboard, announce_winner).chain(),
+                update_speed_tier,
+            ),
+        );
+        // Left until the end of the tick so adaptive difficulty sees how long the rally was
+        app.add_systems(FixedUpdate, end_rally.in_set(GameplaySet::Phase));
+        app.add_systems(OnEnter(AppState::InGame), reset_match);
+        app.add_systems(OnEnter(MatchState::Playing), serve_ball);
+        app.add_systems(
+            FixedUpdate,
+            restart_match
+                .in_set(GameplaySet::Phase)
+                .run_if(in_state(MatchState::GameOver)),
         );
     }
 }
 
 // If the ball is off the screen, check which players side and issue a ScoreEvent with that information
-fn detect_scoring(ball: Query<&Position, With<Ball>>, mut events: EventWriter<ScoreEvent>) {
+fn detect_scoring(
+    ball: Query<&Position, With<Ball>>,
+    mut events: EventWriter<ScoreEvent>,
+    mut next_state: ResMut<NextState<MatchState>>,
+    rules: Res<GameRules>,
+) {
     if let Ok(ball) = ball.get_single() {
-        if ball.0.x > WIN_WIDTH / 2. {
+        if ball.0.x > rules.field_width / 2. {
             events.send(ScoreEvent(Scorer::Ai));
-        } else if ball.0.x < -WIN_WIDTH / 2. {
+            next_state.set(MatchState::PointScored);
+        } else if ball.0.x < -rules.field_width / 2. {
             events.send(ScoreEvent(Scorer::Player));
+            next_state.set(MatchState::PointScored);
         }
     }
 }
@@ -48,27 +79,72 @@ fn update_score(mut score: ResMut<Score>, mut events: EventReader<ScoreEvent>) {
     }
 }
 
-// Return the ball to the center on a ScoreEvent
+// Points end the rally, and with it any speed it built up
+fn end_rally(mut rally: ResMut<Rally>, mut events: EventReader<ScoreEvent>) {
+    if events.read().count() > 0 {
+        *rally = Rally::default();
+    }
+}
+
+// After a point, check if either side has won and end the match if they have
+fn check_match_won(
+    score: Res<Score>,
+    rules: Res<GameRules>,
+    mut events: EventReader<ScoreEvent>,
+    mut won: EventWriter<MatchWon>,
+    mut next_state: ResMut<NextState<MatchState>>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+
+    if let Some(winner) = score.winner(&rules) {
+        won.send(MatchWon(winner));
+        next_state.set(MatchState::GameOver);
+    }
+}
+
+// Return the ball to the center on a ScoreEvent and hold it there until the next serve
 fn reset_ball(
-    mut ball: Query<(&mut Transform, &mut LinearVelocity), With<Ball>>,
+    mut ball: Query<(&mut Transform, &mut LinearVelocity, &mut AngularVelocity), With<Ball>>,
+    mut serve: ResMut<Serve>,
+    rules: Res<GameRules>,
     mut events: EventReader<ScoreEvent>,
 ) {
     for event in events.read() {
-        if let Ok((mut transform, mut velocity)) = ball.get_single_mut() {
+        if let Ok((mut transform, mut velocity, mut spin)) = ball.get_single_mut() {
+            transform.translation = Vec3::ZERO;
+            velocity.0 = Vec2::ZERO;
+            spin.0 = 0.;
+
+            // Serve towards whoever just scored
             match event.0 {
-                Scorer::Ai => {
-                    transform.translation = Vec3::ZERO;
-                    velocity.0 = Vec2::new(-BALL_SPEED, BALL_SPEED);
-                }
-                Scorer::Player => {
-                    transform.translation = Vec3::ZERO;
-                    velocity.0 = Vec2::new(BALL_SPEED, BALL_SPEED);
-                }
+                Scorer::Ai => serve.0 = Vec2::new(-rules.ball_speed, rules.ball_speed),
+                Scorer::Player => serve.0 = Vec2::new(rules.ball_speed, rules.ball_speed),
             }
         }
     }
 }
 
+// Clear the score and serve up-right at the start of every match
+fn reset_match(
+    mut score: ResMut<Score>,
+    mut rally: ResMut<Rally>,
+    mut serve: ResMut<Serve>,
+    rules: Res<GameRules>,
+) {
+    *score = Score::default();
+    *rally = Rally::default();
+    serve.0 = Vec2::new(rules.ball_speed, rules.ball_speed);
+}
+
+// Launch the ball once play starts
+fn serve_ball(mut ball: Query<&mut LinearVelocity, With<Ball>>, serve: Res<Serve>) {
+    if let Ok(mut velocity) = ball.get_single_mut() {
+        velocity.0 = serve.0;
+    }
+}
+
 // When the score changes, update the UI score text
 fn update_scoreboard(mut scoreboard: Query<&mut Text, With<Scoreboard>>, score: Res<Score>) {
     if score.is_changed() {
@@ -77,3 +153,51 @@ fn update_scoreboard(mut scoreboard: Query<&mut Text, With<Scoreboard>>, score:
         }
     }
 }
+
+// Show the rally's speed tier under the score while it's above the first
+fn update_speed_tier(
+    mut text: Query<&mut Text, With<SpeedTierText>>,
+    rally: Res<Rally>,
+    rules: Res<GameRules>,
+) {
+    if rally.is_changed() || rules.is_changed() {
+        if let Ok(mut text) = text.get_single_mut() {
+            text.sections[0].value = match rally.tier(&rules) {
+                0 => String::new(),
+                tier => format!("Speed tier {tier}"),
+            };
+        }
+    }
+}
+
+// Replace the scoreboard with the final result once the match is won
+fn announce_winner(
+    mut scoreboard: Query<&mut Text, With<Scoreboard>>,
+    score: Res<Score>,
+    mut events: EventReader<MatchWon>,
+) {
+    for MatchWon(winner) in events.read() {
+        if let Ok(mut text) = scoreboard.get_single_mut() {
+            let name = match winner {
+                Scorer::Ai => "AI",
+                Scorer::Player => "Player",
+            };
+            text.sections[0].value = format!(
+                "{} - {}\n{name} wins! Press serve to play again",
+                score.player, score.ai
+            );
+        }
+    }
+}
+
+// Wait for the player to start a new match after a game over
+fn restart_match(
+    tick_input: Res<TickInput>,
+    mut score: ResMut<Score>,
+    mut next_state: ResMut<NextState<MatchState>>,
+) {
+    if tick_input.left.serve || tick_input.right.serve {
+        *score = Score::default();
+        next_state.set(MatchState::Serving);
+    }
+}
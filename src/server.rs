@@ -0,0 +1,431 @@
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use avian2d::prelude::LinearVelocity;
+use bevy::{
+    ecs::event::{Events, ManualEventReader},
+    math::Vec2,
+    prelude::{App, Transform, With, World},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    headless::{headless_app, TICK},
+    rules::GameRules,
+    structure::*,
+};
+
+// Port pong-server listens on unless told otherwise
+pub const DEFAULT_PORT: u16 = 7878;
+
+// Match codes are short enough to read out across a room. No I or O, they look like 1 and 0
+const CODE_LETTERS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+const CODE_LENGTH: usize = 4;
+
+// Longest line a connection will wait for the end of. Real messages are a few hundred bytes
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+// Most bytes a connection holds on to for a peer that isn't reading them
+const MAX_UNSENT: usize = 1024 * 1024;
+
+// ##############################################################
+// # Protocol
+// ##############################################################
+
+// Everything a client can ask of the server
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ClientMessage {
+    Host,         // Open a new match and wait for someone to join it
+    Join(String), // Join the open match with this code
+    List,         // Ask for the codes of every match waiting for a second player
+    Input { movement: f32, serve: bool },
+}
+
+// Everything the server tells its clients
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ServerMessage {
+    Hosted(String),    // Code the other player needs to join
+    Open(Vec<String>), // Codes of matches waiting for a second player
+    Refused(String),   // Why a join didn't work, the client stays in the lobby
+    Started { side: Side, rules: GameRules },
+    Snapshot(MatchSnapshot),
+    MatchWon(Scorer),
+    Closed(String), // Why the match ended
+}
+
+// The state of a match after one server tick, all a client needs to draw it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MatchSnapshot {
+    pub tick: u64,
+    pub ball: Vec2,
+    pub ball_velocity: Vec2,
+    pub left: Vec2,  // Left paddle position
+    pub right: Vec2, // Right paddle position
+    pub score: Score,
+    pub rally: Rally,
+}
+
+impl MatchSnapshot {
+    // Reads Transform, which is up to date once the tick is over. Position isn't synced from it
+    // until the start of the next tick
+    pub fn take(world: &mut World) -> Option<Self> {
+        let (ball, ball_velocity) = world
+            .query_filtered::<(&Transform, &LinearVelocity), With<Ball>>()
+            .get_single(world)
+            .ok()
+            .map(|(transform, velocity)| (transform.translation.truncate(), velocity.0))?;
+
+        let mut left = Vec2::ZERO;
+        let mut right = Vec2::ZERO;
+        let mut paddles = world.query_filtered::<(&Transform, &Side), With<Paddle>>();
+        for (transform, side) in paddles.iter(world) {
+            match side {
+                Side::Left => left = transform.translation.truncate(),
+                Side::Right => right = transform.translation.truncate(),
+            }
+        }
+
+        Some(Self {
+            tick: world.resource::<Tick>().0,
+            ball,
+            ball_velocity,
+            left,
+            right,
+            score: world.resource::<Score>().clone(),
+            rally: *world.resource::<Rally>(),
+        })
+    }
+}
+
+// A TCP stream carrying one RON encoded message per line, read and written without blocking
+pub struct Connection {
+    stream: TcpStream,
+    received: Vec<u8>,
+    unsent: Vec<u8>, // Written once the other end has room for it
+    pub closed: bool,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Result<Self, io::Error> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            received: Vec::new(),
+            unsent: Vec::new(),
+            closed: false,
+        })
+    }
+
+    pub fn connect(addr: SocketAddr) -> Result<Self, io::Error> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    pub fn send<T: Serialize>(&mut self, message: &T) {
+        if self.closed {
+            return;
+        }
+
+        let mut line = ron::to_string(message).expect("messages always serialize");
+        line.push('\n');
+        self.unsent.extend_from_slice(line.as_bytes());
+        self.flush();
+    }
+
+    // Write as much of what's queued as the send buffer takes
+    fn flush(&mut self) {
+        while !self.closed && !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(0) => self.closed = true,
+                Ok(written) => {
+                    self.unsent.drain(..written);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(_) => self.closed = true,
+            }
+        }
+
+        // Whoever is on the other end stopped reading a long time ago
+        if self.unsent.len() > MAX_UNSENT {
+            self.closed = true;
+        }
+    }
+
+    // Every complete message that has arrived since the last call
+    pub fn receive<T: DeserializeOwned>(&mut self) -> Vec<T> {
+        self.flush();
+
+        let mut messages = Vec::new();
+        let mut buffer = [0; 4096];
+        while !self.closed {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(read) => {
+                    self.received.extend_from_slice(&buffer[..read]);
+                    self.split_lines(&mut messages);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(_) => self.closed = true,
+            }
+        }
+        messages
+    }
+
+    // Parse every complete line received so far
+    fn split_lines<T: DeserializeOwned>(&mut self, messages: &mut Vec<T>) {
+        while let Some(end) = self.received.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.received.drain(..=end).collect();
+            let message = std::str::from_utf8(&line)
+                .ok()
+                .and_then(|line| ron::from_str(line.trim()).ok());
+
+            match message {
+                Some(message) => messages.push(message),
+                // Whoever is on the other end isn't speaking our protocol
+                None => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        // No message is this long, so it's never going to end
+        if self.received.len() > MAX_LINE_LENGTH {
+            self.closed = true;
+        }
+    }
+}
+
+// ##############################################################
+// # Server
+// ##############################################################
+
+// Runs any number of matches headless, each between two clients paired up in the lobby by
+// a match code. The server's simulation is the only one that counts, clients just draw it
+pub struct PongServer {
+    listener: TcpListener,
+    rules: GameRules,
+    rng: StdRng,
+    lobby: Vec<Connection>,            // Connected but not in a match
+    open: HashMap<String, Connection>, // Hosts waiting for someone to join their code
+    matches: Vec<ServerMatch>,
+}
+
+impl PongServer {
+    pub fn bind(port: u16, rules: GameRules) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            rules,
+            rng: StdRng::from_entropy(),
+            lobby: Vec::new(),
+            open: HashMap::new(),
+            matches: Vec::new(),
+        })
+    }
+
+    // Serve forever, stepping every match once per tick
+    pub fn run(&mut self) -> ! {
+        loop {
+            let started = Instant::now();
+            self.update();
+            thread::sleep(TICK.saturating_sub(started.elapsed()));
+        }
+    }
+
+    pub fn update(&mut self) {
+        self.accept();
+        self.update_lobby();
+        self.matches.retain_mut(ServerMatch::update);
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => match Connection::new(stream) {
+                    Ok(connection) => {
+                        println!("{addr} connected");
+                        self.lobby.push(connection);
+                    }
+                    Err(err) => eprintln!("Couldn't set up connection from {addr}: {err}"),
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    eprintln!("Couldn't accept a connection: {err}");
+                    break;
+                }
+            }
+        }
+    }
+
+    fn update_lobby(&mut self) {
+        for connection in std::mem::take(&mut self.lobby) {
+            self.handle_lobby_messages(connection);
+        }
+
+        // Hosts only ever wait, but drop the code if they give up
+        self.open.retain(|code, host| {
+            host.receive::<ClientMessage>();
+            if host.closed {
+                println!("Match {code} closed before anyone joined");
+            }
+            !host.closed
+        });
+    }
+
+    fn handle_lobby_messages(&mut self, mut connection: Connection) {
+        for message in connection.receive::<ClientMessage>() {
+            match message {
+                ClientMessage::Host => {
+                    let code = self.new_code();
+                    println!("Match {code} opened");
+                    connection.send(&ServerMessage::Hosted(code.clone()));
+                    self.open.insert(code, connection);
+                    return;
+                }
+                ClientMessage::Join(code) => {
+                    let code = code.trim().to_uppercase();
+                    if let Some(host) = self.open.remove(&code) {
+                        self.start_match(code, host, connection);
+                        return;
+                    }
+                    connection.send(&ServerMessage::Refused(format!(
+                        "No open match with code {code}"
+                    )));
+                }
+                ClientMessage::List => {
+                    let codes = self.open.keys().cloned().collect();
+                    connection.send(&ServerMessage::Open(codes));
+                }
+                ClientMessage::Input { .. } => (),
+            }
+        }
+
+        if !connection.closed {
+            self.lobby.push(connection);
+        }
+    }
+
+    fn new_code(&mut self) -> String {
+        loop {
+            let code: String = (0..CODE_LENGTH)
+                .map(|_| CODE_LETTERS[self.rng.gen_range(0..CODE_LETTERS.len())] as char)
+                .collect();
+            if !self.open.contains_key(&code) {
+                return code;
+            }
+        }
+    }
+
+    // The host plays the left paddle and whoever joined plays the right
+    fn start_match(&mut self, code: String, mut host: Connection, mut guest: Connection) {
+        println!("Match {code} started");
+
+        for (side, client) in [(Side::Left, &mut host), (Side::Right, &mut guest)] {
+            client.send(&ServerMessage::Started {
+                side,
+                rules: self.rules.clone(),
+            });
+        }
+
+        // Both paddles are Players, steered by the TickInput the clients send
+        let mut app = headless_app(self.rules.clone(), GameMode::LocalVersus, self.rng.gen());
+        app.insert_resource(ExternalInput);
+
+        self.matches.push(ServerMatch {
+            code,
+            app,
+            clients: [host, guest],
+            inputs: TickInput::default(),
+            match_won: ManualEventReader::default(),
+        });
+    }
+}
+
+struct ServerMatch {
+    code: String,
+    app: App,
+    clients: [Connection; 2], // Left then right
+    inputs: TickInput,
+    match_won: ManualEventReader<MatchWon>,
+}
+
+impl ServerMatch {
+    // Step the match one tick and tell the clients about it. False once it's over
+    fn update(&mut self) -> bool {
+        for (side, client) in [Side::Left, Side::Right].into_iter().zip(&mut self.clients) {
+            for message in client.receive::<ClientMessage>() {
+                if let ClientMessage::Input { movement, serve } = message {
+                    let input = self.inputs.side_mut(side);
+                    input.movement = clamp_movement(movement);
+                    input.serve |= serve;
+                }
+            }
+        }
+
+        if self.clients.iter().any(|client| client.closed) {
+            println!("Match {} ended, a player left", self.code);
+            for client in &mut self.clients {
+                client.send(&ServerMessage::Closed(String::from("Your opponent left")));
+            }
+            return false;
+        }
+
+        // Serves are only held until a tick has seen them
+        *self.app.world_mut().resource_mut::<TickInput>() = self.inputs;
+        self.inputs.left.serve = false;
+        self.inputs.right.serve = false;
+
+        self.app.update();
+
+        let snapshot = MatchSnapshot::take(self.app.world_mut());
+        let events = self.app.world().resource::<Events<MatchWon>>();
+        let winner = self
+            .match_won
+            .read(events)
+            .last()
+            .map(|MatchWon(winner)| *winner);
+
+        for client in &mut self.clients {
+            if let Some(snapshot) = &snapshot {
+                client.send(&ServerMessage::Snapshot(snapshot.clone()));
+            }
+            if let Some(winner) = winner {
+                client.send(&ServerMessage::MatchWon(winner));
+            }
+        }
+
+        true
+    }
+}
+
+// Ask the server at addr which matches are waiting for a second player
+pub fn open_matches(addr: SocketAddr, timeout: Duration) -> Result<Vec<String>, io::Error> {
+    let mut connection = Connection::connect(addr)?;
+    connection.send(&ClientMessage::List);
+
+    let started = Instant::now();
+    while !connection.closed && started.elapsed() < timeout {
+        for message in connection.receive::<ServerMessage>() {
+            if let ServerMessage::Open(codes) = message {
+                return Ok(codes);
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    Err(io::Error::new(
+        ErrorKind::TimedOut,
+        "the server didn't list its matches",
+    ))
+}
@@ -1,34 +1,35 @@
-use avian2d::prelude::Gravity;
+use avian2d::prelude::{Collider, Gravity};
 use bevy::{
-    app::{App, Startup},
-    asset::{AssetServer, Assets},
+    app::{App, Update},
     ecs::{
-        change_detection::ResMut,
+        query::With,
+        schedule::IntoSystemConfigs,
         system::{Commands, Query},
     },
+    hierarchy::BuildChildren,
     math::Vec2,
     prelude::{
-        default, Camera2dBundle, Color, ColorMaterial, JustifyText, Mesh, Plugin, Rectangle, Res,
-        Text, Text2dBundle, TextStyle, Transform, Window,
+        default, in_state, resource_changed, Color, JustifyText, NodeBundle, OnEnter, Plugin, Res,
+        ResMut, StateScoped, TextBundle, TextStyle, Transform,
     },
-    sprite::{Anchor, MaterialMesh2dBundle, Sprite, SpriteBundle},
+    ui::{AlignItems, FlexDirection, PositionType, Style, Val},
 };
 
-use crate::structure::*;
+use crate::{rules::GameRules, structure::*};
 
 pub struct PongInitPlugin;
 
 impl Plugin for PongInitPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
-            Startup,
-            (
-                spawn_camera,
-                spawn_ball,
-                spawn_paddles,
-                spawn_gutters,
-                spawn_scoreboard,
-            ),
+            OnEnter(AppState::InGame),
+            (spawn_ball, spawn_paddles, spawn_gutters, spawn_scoreboard),
+        );
+        app.add_systems(
+            Update,
+            (resize_ball, resize_paddles, resize_gutters)
+                .run_if(resource_changed::<GameRules>)
+                .run_if(in_state(AppState::InGame)),
         );
         app.insert_resource(Gravity(Vec2::ZERO));
     }
@@ -38,157 +39,174 @@ impl Plugin for PongInitPlugin {
 // # Setup
 // ##############################################################
 
-// Create scene camera
-pub fn spawn_camera(mut commands: Commands) {
-    commands.spawn_empty().insert(Camera2dBundle::default());
-}
+// Everything here is laid out from the field dimensions in GameRules rather than the window,
+// so it works the same without one. Sprites and meshes are added by PongVisualsPlugin
 
-// Spawn a red pong ball at the center of the screen moving up-right
-pub fn spawn_ball(
-    mut commands: Commands,
-    // mut meshes: ResMut<Assets<Mesh>>,
-    // mut materials: ResMut<Assets<ColorMaterial>>,
-    asset_server: Res<AssetServer>,
-) {
+// Spawn the pong ball at the center of the field, waiting to be served
+pub fn spawn_ball(mut commands: Commands, rules: Res<GameRules>) {
     println!("Spawning ball...");
 
-    let ball = BallBundle::new(150., 150.);
-
-    // Define ball mesh and material
-    //let shape = Mesh::from(Circle::new(ball.shape.0.x));
-    //let color = ColorMaterial::from(Color::hsl(0., 1., 0.28));
-
-    // Instance mesh into memory and return Handle reference
-    //let mesh_handle = meshes.add(shape);
-    //let material_handle = materials.add(color);
-
     commands.spawn((
-        ball,
-        SpriteBundle {
-            texture: asset_server.load("ball.png"),
-            sprite: Sprite {
-                custom_size: Some((BALL_SIZE, BALL_SIZE).into()),
-                ..default()
-            },
-            ..default()
-        }, // MaterialMesh2dBundle {
-           //     mesh: mesh_handle.into(),
-           //     material: material_handle,
-           //     transform: Transform::from_translation(Vec3::ZERO),
-           //     ..default()
-           // },
+        BallBundle::new(0., 0., rules.ball_size),
+        StateScoped(AppState::InGame),
     ));
 }
 
-// Spawn Player and AI paddles at the left and right edge of the screen
+// Spawn paddles at the left and right edge of the field, controlled according to the GameMode
 pub fn spawn_paddles(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    window: Query<&Window>,
+    rules: Res<GameRules>,
+    mode: Res<GameMode>,
+    mut rng: ResMut<GameRng>,
 ) {
     println!("Spawning paddles...");
 
-    if let Ok(window) = window.get_single() {
-        let window_width = window.resolution.width();
-        let padding = 50.;
-        let right_paddle_x = window_width / 2. - padding;
-        let left_paddle_x = -window_width / 2. + padding;
-
-        let player_paddle = PaddleBundle::new(right_paddle_x, 0.);
-        let ai_paddle = PaddleBundle::new(left_paddle_x, 0.);
-
-        let shape = Mesh::from(Rectangle::new(ai_paddle.shape.0.x, ai_paddle.shape.0.y));
-        let color = ColorMaterial::from(Color::WHITE);
-
-        // Instance mesh into memory and return Handle reference
-        let mesh_handle = meshes.add(shape);
-        let material_handle = materials.add(color);
-
-        commands.spawn((
-            Player,
-            player_paddle,
-            MaterialMesh2dBundle {
-                mesh: mesh_handle.clone().into(),
-                material: material_handle.clone(),
-                transform: Transform::from_xyz(right_paddle_x, 0., 0.),
-                ..default()
-            },
-        ));
-
-        commands.spawn((
-            Ai,
-            ai_paddle,
-            MaterialMesh2dBundle {
-                mesh: mesh_handle.into(),
-                material: material_handle,
-                transform: Transform::from_xyz(left_paddle_x, 0., 0.),
-                ..default()
-            },
-        ));
-    }
-}
+    let right_paddle_x = rules.field_width / 2. - rules.paddle_inset;
+    let left_paddle_x = -rules.field_width / 2. + rules.paddle_inset;
 
-// Spawn gutters at the top and bottom of the screen
-pub fn spawn_gutters(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-) {
-    // Window coordinates have the corner be 0,0 while world coordinates that's the center so
-    // we devide by two to convert
-    let top_gutter_y = WIN_HEIGHT / 2. - GUTTER_HEIGHT / 2.;
-    let bottom_gutter_y = -WIN_HEIGHT / 2. + GUTTER_HEIGHT / 2.;
+    let mut right_paddle = commands.spawn((
+        PaddleBundle::new(right_paddle_x, 0., rules.paddle_width, rules.paddle_height),
+        StateScoped(AppState::InGame),
+    ));
 
-    let top_gutter = GutterBundle::new(0., top_gutter_y, WIN_WIDTH);
-    let bottom_gutter = GutterBundle::new(0., bottom_gutter_y, WIN_WIDTH);
+    // The right paddle is player one's unless nobody is playing
+    match *mode {
+        GameMode::VsAi | GameMode::LocalVersus | GameMode::Online => right_paddle.insert(Player),
+        GameMode::AiVersusAi => {
+            right_paddle.insert((Ai, rules.ai_personality.controller(&mut rng)))
+        }
+    };
+
+    let mut left_paddle = commands.spawn((
+        PaddleBundle::new(left_paddle_x, 0., rules.paddle_width, rules.paddle_height),
+        StateScoped(AppState::InGame),
+    ));
 
-    let mesh = Mesh::from(Rectangle::from_size(top_gutter.shape.0));
-    let material = ColorMaterial::from(Color::WHITE);
+    // The left paddle is only a second player's in local versus and online
+    match *mode {
+        GameMode::VsAi | GameMode::AiVersusAi => {
+            left_paddle.insert((Ai, rules.ai_personality.controller(&mut rng)))
+        }
+        GameMode::LocalVersus | GameMode::Online => left_paddle.insert(Player),
+    };
+}
 
-    let mesh_handle = meshes.add(mesh);
-    let material_handle = materials.add(material);
+// Spawn gutters at the top and bottom of the field
+pub fn spawn_gutters(mut commands: Commands, rules: Res<GameRules>) {
+    // Window coordinates have the corner be 0,0 while world coordinates that's the center so
+    // we devide by two to convert
+    let top_gutter_y = rules.field_height / 2. - rules.gutter_height / 2.;
+    let bottom_gutter_y = -rules.field_height / 2. + rules.gutter_height / 2.;
 
     commands.spawn((
-        top_gutter,
-        MaterialMesh2dBundle {
-            mesh: mesh_handle.clone().into(),
-            material: material_handle.clone(),
-            transform: Transform::from_xyz(0., top_gutter_y, 0.),
-            ..default()
-        },
+        GutterBundle::new(0., top_gutter_y, rules.field_width, rules.gutter_height),
+        StateScoped(AppState::InGame),
     ));
 
     commands.spawn((
-        bottom_gutter,
-        MaterialMesh2dBundle {
-            mesh: mesh_handle.into(),
-            material: material_handle,
-            transform: Transform::from_xyz(0., bottom_gutter_y, 0.),
-            ..default()
-        },
+        GutterBundle::new(0., bottom_gutter_y, rules.field_width, rules.gutter_height),
+        StateScoped(AppState::InGame),
     ));
 }
 
-// Spawn a scoreboard displaying the game score in the format {ai} - {player} at the top of the screen
-pub fn spawn_scoreboard(mut commands: Commands) {
-    let text_pos = Transform::from_xyz(0., (WIN_HEIGHT / 2.) - 20., 0.);
-
-    commands.spawn((
-        Text2dBundle {
-            text: Text::from_section(
-                "0 - 0",
-                TextStyle {
-                    font_size: 48.0,
-                    color: Color::WHITE,
+// Spawn a scoreboard displaying the game score in the format {player} - {ai} at the top of the screen,
+// with the rally's speed tier under it
+pub fn spawn_scoreboard(mut commands: Commands, rules: Res<GameRules>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.),
+                    top: Val::Px(rules.gutter_height),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
                     ..default()
                 },
-            )
-            .with_justify(JustifyText::Center),
-            text_anchor: Anchor::TopCenter,
-            transform: text_pos,
-            ..default()
-        },
-        Scoreboard,
-    ));
+                ..default()
+            },
+            StateScoped(AppState::InGame),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "0 - 0",
+                    TextStyle {
+                        font_size: 48.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                )
+                .with_text_justify(JustifyText::Center),
+                Scoreboard,
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 24.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                SpeedTierText,
+            ));
+        });
+}
+
+// ##############################################################
+// # Rule Changes
+// ##############################################################
+
+// Rebuild the ball's collider if its size changed
+pub fn resize_ball(
+    mut ball: Query<(&mut Shape, &mut Collider), With<Ball>>,
+    rules: Res<GameRules>,
+) {
+    let size = Vec2::splat(rules.ball_size);
+
+    for (mut shape, mut collider) in &mut ball {
+        if shape.0 != size {
+            shape.0 = size;
+            *collider = Collider::circle(rules.ball_size);
+        }
+    }
+}
+
+// Rebuild the paddle colliders if their size changed, and move them back inside the field
+pub fn resize_paddles(
+    mut paddles: Query<(&mut Shape, &mut Collider, &mut Transform), With<Paddle>>,
+    rules: Res<GameRules>,
+) {
+    let size = Vec2::new(rules.paddle_width, rules.paddle_height);
+    let max_y = rules.paddle_max_y();
+    let paddle_x = rules.field_width / 2. - rules.paddle_inset;
+
+    for (mut shape, mut collider, mut transform) in &mut paddles {
+        if shape.0 != size {
+            shape.0 = size;
+            *collider = Collider::rectangle(size.x, size.y);
+        }
+
+        transform.translation.x = paddle_x * transform.translation.x.signum();
+        transform.translation.y = transform.translation.y.clamp(-max_y, max_y);
+    }
+}
+
+// Rebuild the gutter colliders if their size changed, keeping them at the edges of the field
+pub fn resize_gutters(
+    mut gutters: Query<(&mut Shape, &mut Collider, &mut Transform), With<Gutter>>,
+    rules: Res<GameRules>,
+) {
+    let size = Vec2::new(rules.field_width, rules.gutter_height);
+    let gutter_y = rules.field_height / 2. - rules.gutter_height / 2.;
+
+    for (mut shape, mut collider, mut transform) in &mut gutters {
+        if shape.0 != size {
+            shape.0 = size;
+            *collider = Collider::rectangle(size.x, size.y);
+        }
+
+        transform.translation.y = gutter_y * transform.translation.y.signum();
+    }
 }
@@ -0,0 +1,269 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind},
+    net::{SocketAddr, TcpListener},
+    time::Duration,
+};
+
+use bevy::{
+    app::{App, FixedUpdate, Update},
+    ecs::event::{Events, ManualEventReader},
+    prelude::{
+        in_state, not, resource_exists, Commands, EventWriter, IntoSystemConfigs, Mut, NextState,
+        OnEnter, OnExit, Plugin, Query, Res, ResMut, Resource, State, Transform, With, Without,
+        World,
+    },
+    time::{Time, Virtual},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{draw_snapshot, SnapshotBuffer},
+    netcode::resimulating,
+    rules::GameRules,
+    server::{Connection, MatchSnapshot},
+    structure::*,
+};
+
+pub struct PongSpectatePlugin;
+
+impl Plugin for PongSpectatePlugin {
+    fn build(&self, app: &mut App) {
+        // Broadcasting a match being played here
+        app.add_systems(
+            OnEnter(AppState::InGame),
+            send_rules.run_if(resource_exists::<SpectatorBroadcast>),
+        );
+        app.add_systems(
+            FixedUpdate,
+            broadcast_match
+                .after(GameplaySet::Phase)
+                .run_if(resource_exists::<SpectatorBroadcast>)
+                .run_if(not(resimulating))
+                .run_if(in_state(AppState::InGame)),
+        );
+
+        // Watching someone else's broadcast
+        app.add_systems(
+            Update,
+            (
+                receive_broadcast,
+                (draw_spectated, play_spectated_events).run_if(in_state(AppState::InGame)),
+            )
+                .chain()
+                .run_if(resource_exists::<Spectating>),
+        );
+        app.add_systems(OnExit(AppState::InGame), stop_spectating);
+    }
+}
+
+// Everything a spectator is sent
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum SpectatorMessage {
+    Rules(GameRules),        // When a spectator connects and whenever a match starts
+    Snapshot(MatchSnapshot), // Carries the score, so points don't need a message of their own
+    MatchWon(Scorer),
+}
+
+// ##############################################################
+// # Broadcasting
+// ##############################################################
+
+// Streams every tick of the matches played here to anyone who connects, so they can be
+// watched on another screen without touching this one
+#[derive(Resource)]
+pub struct SpectatorBroadcast {
+    listener: TcpListener,
+    spectators: Vec<Connection>,
+    match_won: ManualEventReader<MatchWon>,
+}
+
+impl SpectatorBroadcast {
+    pub fn bind(port: u16) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        println!("Broadcasting to spectators on port {port}");
+
+        Ok(Self {
+            listener,
+            spectators: Vec::new(),
+            match_won: ManualEventReader::default(),
+        })
+    }
+
+    fn accept(&mut self, rules: &GameRules) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => match Connection::new(stream) {
+                    Ok(mut spectator) => {
+                        println!("Spectator {addr} connected");
+                        spectator.send(&SpectatorMessage::Rules(rules.clone()));
+                        self.spectators.push(spectator);
+                    }
+                    Err(err) => eprintln!("Couldn't set up spectator {addr}: {err}"),
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    eprintln!("Couldn't accept a spectator: {err}");
+                    break;
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, message: &SpectatorMessage) {
+        for spectator in &mut self.spectators {
+            spectator.send(message);
+        }
+    }
+}
+
+// Spectators need the rules to lay the field out, and they may have changed since last match
+fn send_rules(mut broadcast: ResMut<SpectatorBroadcast>, rules: Res<GameRules>) {
+    broadcast.send(&SpectatorMessage::Rules(rules.clone()));
+}
+
+// Once a tick, after everything has moved and been scored
+fn broadcast_match(world: &mut World) {
+    world.resource_scope(|world, mut broadcast: Mut<SpectatorBroadcast>| {
+        broadcast.accept(world.resource::<GameRules>());
+
+        let broadcast = &mut *broadcast;
+        let winners: Vec<Scorer> = broadcast
+            .match_won
+            .read(world.resource::<Events<MatchWon>>())
+            .map(|MatchWon(winner)| *winner)
+            .collect();
+
+        let Some(snapshot) = MatchSnapshot::take(world) else {
+            return;
+        };
+
+        broadcast.send(&SpectatorMessage::Snapshot(snapshot));
+        for winner in winners {
+            broadcast.send(&SpectatorMessage::MatchWon(winner));
+        }
+
+        broadcast.spectators.retain(|spectator| {
+            if spectator.closed {
+                println!("Spectator disconnected");
+            }
+            !spectator.closed
+        });
+    });
+}
+
+// ##############################################################
+// # Spectating
+// ##############################################################
+
+// Watching a match broadcast from another game. Nothing is simulated or controlled here, the
+// field is drawn from the stream a fixed delay behind it
+#[derive(Resource)]
+pub struct Spectating {
+    connection: Connection,
+    snapshots: SnapshotBuffer,
+    events: VecDeque<(u64, SpectatorMessage)>, // Wins waiting to be drawn, by tick
+    newest_tick: u64,
+    ended: bool,
+}
+
+impl Spectating {
+    pub fn connect(addr: SocketAddr, delay: Duration) -> Result<Self, io::Error> {
+        println!("Spectating {addr} {:.1}s behind", delay.as_secs_f32());
+
+        Ok(Self {
+            connection: Connection::connect(addr)?,
+            snapshots: SnapshotBuffer::new(delay),
+            events: VecDeque::new(),
+            newest_tick: 0,
+            ended: false,
+        })
+    }
+}
+
+fn receive_broadcast(
+    mut spectating: ResMut<Spectating>,
+    mut rules: ResMut<GameRules>,
+    app_state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    for message in spectating.connection.receive::<SpectatorMessage>() {
+        match message {
+            SpectatorMessage::Rules(new_rules) => {
+                if *rules != new_rules {
+                    *rules = new_rules;
+                }
+                // The broadcaster runs the match, here it's only drawn
+                time.set_relative_speed(0.);
+                if *app_state.get() != AppState::InGame {
+                    next_state.set(AppState::InGame);
+                }
+            }
+            SpectatorMessage::Snapshot(snapshot) => {
+                // Events from ticks that are being replaced will be sent again
+                spectating.events.retain(|(tick, _)| *tick < snapshot.tick);
+                spectating.newest_tick = snapshot.tick;
+                spectating.snapshots.push(snapshot);
+            }
+            SpectatorMessage::MatchWon(_) => {
+                let tick = spectating.newest_tick;
+                spectating.events.push_back((tick, message));
+            }
+        }
+    }
+
+    if spectating.connection.closed && !spectating.ended {
+        println!("The broadcast ended");
+        spectating.ended = true;
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn draw_spectated(
+    spectating: Res<Spectating>,
+    mut score: ResMut<Score>,
+    mut rally: ResMut<Rally>,
+    mut balls: Query<&mut Transform, With<Ball>>,
+    mut paddles: Query<(&mut Transform, &Side), (With<Paddle>, Without<Ball>)>,
+) {
+    if let Some(snapshot) = spectating.snapshots.interpolated() {
+        if *score != snapshot.score {
+            *score = snapshot.score.clone();
+        }
+        if *rally != snapshot.rally {
+            *rally = snapshot.rally;
+        }
+        draw_snapshot(&snapshot, &mut balls, &mut paddles);
+    }
+}
+
+// Hand on wins once the delayed field has caught up with them
+// Only for Update systems like announce_winner, FixedUpdate is frozen while spectating
+fn play_spectated_events(mut spectating: ResMut<Spectating>, mut match_won: EventWriter<MatchWon>) {
+    let Some(render_tick) = spectating.snapshots.render_tick() else {
+        return;
+    };
+
+    while spectating
+        .events
+        .front()
+        .is_some_and(|(tick, _)| *tick as f64 <= render_tick)
+    {
+        if let Some((_, SpectatorMessage::MatchWon(winner))) = spectating.events.pop_front() {
+            match_won.send(MatchWon(winner));
+        }
+    }
+}
+
+fn stop_spectating(
+    mut commands: Commands,
+    spectating: Option<Res<Spectating>>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if spectating.is_some() {
+        commands.remove_resource::<Spectating>();
+        time.set_relative_speed(1.);
+    }
+}
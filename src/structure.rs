@@ -1,38 +1,150 @@
-use avian2d::prelude::{Collider, Friction, LinearVelocity, Restitution, RigidBody};
+use std::net::SocketAddr;
+
+use avian2d::prelude::{
+    AngularVelocity, Collider, CollisionLayers, Friction, LinearVelocity, PhysicsLayer,
+    Restitution, RigidBody, Sensor, SweptCcd,
+};
 use bevy::{
     ecs::{component::Component, event::Event},
     math::Vec2,
-    prelude::{Bundle, Resource},
+    prelude::{
+        Bundle, Resource, StateSet, States, SubStates, SystemSet, Transform, TransformBundle,
+    },
+    time::{Timer, TimerMode},
 };
 
-// ##############################################################
-// # Global Constants
-// ##############################################################
-
-pub const WIN_HEIGHT: f32 = 720.;
-pub const WIN_WIDTH: f32 = 1280.;
-
-pub const BALL_SIZE: f32 = 10.; // Size of the ball in world units
-pub const BALL_SPEED: f32 = 200.; // Speed per frame of the ball in world units
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
-pub const PADDLE_SPEED: f32 = 5.; // Speed per frame of the paddles in world units
-pub const PADDLE_WIDTH: f32 = 10.; // Dimensions of the paddles in world units
-pub const PADDLE_HEIGHT: f32 = 50.;
-
-pub const AI_SKILL: f32 = 15.; // Adjusts how rapidly the AI's smoothing function responds
-
-pub const GUTTER_HEIGHT: f32 = 20.; // Height of the top and bottom gutters in world units
+use crate::rules::GameRules;
 
 // ##############################################################
 // # Helper Types
 // ##############################################################
 
 // Used to communicate which player scored in ScoreEvent
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Scorer {
     Ai,
     Player,
 }
 
+// Which screen the app is currently on
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    #[default]
+    MainMenu,
+    Settings,
+    Controls,
+    LanGames,
+    InGame,
+}
+
+// What happens when a MenuButton is activated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuAction {
+    Play(GameMode),
+    Settings,
+    Controls,
+    Quit,
+    Resume,
+    MainMenu,
+    Setting(Setting),
+    Rebind(Side, Action),
+    TogglePointer,
+    LanGames,
+    JoinLan(SocketAddr),
+}
+
+// Which half of the field a paddle defends
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+// Logical inputs a player can bind keys to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    Serve,
+    Pause,
+}
+
+// Runtime adjustable values shown on the settings screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Setting {
+    BallSpeed,
+    PaddleHeight,
+    AiSkill,
+    AiPersonality,
+}
+
+// Phase of the current match, used to decide which systems get to run
+#[derive(SubStates, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[source(AppState = AppState::InGame)]
+pub enum MatchState {
+    #[default]
+    Serving, // Ball is held at the center until the serve delay runs out
+    Playing,     // Ball is in play
+    PointScored, // Short pause after a point before going back to serving
+    GameOver,    // Someone won, waiting for a restart
+}
+
+// Whether the match is running or frozen behind the pause menu
+#[derive(SubStates, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[source(AppState = AppState::InGame)]
+pub enum PauseState {
+    #[default]
+    Running,
+    Paused,
+}
+
+// Who is controlling each paddle
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    #[default]
+    VsAi,
+    LocalVersus,
+    AiVersusAi, // Both paddles are AI, used by headless simulations
+    Online,     // One paddle each for this player and a peer over the network
+}
+
+// What each collider is, for the physics. The ball hits everything else, and nothing else hits
+// each other
+#[derive(Clone, Copy, Debug)]
+pub enum Layer {
+    Ball,
+    Paddle,
+    Gutter,
+}
+
+// Written out instead of derived, avian's derive checks its own features from inside this crate
+// and trips the unexpected_cfgs lint
+impl PhysicsLayer for Layer {
+    fn to_bits(&self) -> u32 {
+        1 << *self as u32
+    }
+
+    fn all_bits() -> u32 {
+        0b111
+    }
+}
+
+// Order gameplay runs in within each FixedUpdate tick, so a tick always plays out the same way
+// Physics runs after all of these, in FixedPostUpdate
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameplaySet {
+    Sync,       // Copy engine positions into Position
+    Input,      // Turn this tick's inputs and AI decisions into paddle velocities
+    Movement,   // Move the paddles
+    Ball,       // React to the ball's collisions from the last physics step
+    Scoring,    // Detect points and update the score
+    Difficulty, // Adjust the AI after points
+    Phase,      // Advance the MatchState
+}
+
 // ##############################################################
 // # Global Events & Resources
 // ##############################################################
@@ -41,13 +153,137 @@ pub enum Scorer {
 #[derive(Event)]
 pub struct ScoreEvent(pub Scorer);
 
+// Notify systems that the ball was returned, and by which side's paddle
+#[derive(Event)]
+pub struct PaddleHit(pub Side);
+
+// Notify systems that the match is over and who won it
+#[derive(Event)]
+pub struct MatchWon(pub Scorer);
+
 // Global store of game score
-#[derive(Resource, Default)]
+#[derive(Resource, Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
 pub struct Score {
     pub player: u32,
     pub ai: u32,
 }
 
+impl Score {
+    // Returns who won the match, if anyone has yet
+    pub fn winner(&self, rules: &GameRules) -> Option<Scorer> {
+        let lead = if rules.win_by_two { 2 } else { 1 };
+
+        if self.player >= rules.target_score && self.player >= self.ai + lead {
+            Some(Scorer::Player)
+        } else if self.ai >= rules.target_score && self.ai >= self.player + lead {
+            Some(Scorer::Ai)
+        } else {
+            None
+        }
+    }
+}
+
+// What one side asked for during a tick
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct SideInput {
+    pub movement: f32, // Paddle velocity between -1 and 1
+    pub serve: bool,   // Serve was pressed since the last tick
+}
+
+// Player input for the next FixedUpdate tick. Sampled from the devices every frame, and the
+// only way player input reaches gameplay so a match can be repeated from its inputs
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq)]
+pub struct TickInput {
+    pub left: SideInput,
+    pub right: SideInput,
+}
+
+impl TickInput {
+    pub fn side(&self, side: Side) -> &SideInput {
+        match side {
+            Side::Left => &self.left,
+            Side::Right => &self.right,
+        }
+    }
+
+    pub fn side_mut(&mut self, side: Side) -> &mut SideInput {
+        match side {
+            Side::Left => &mut self.left,
+            Side::Right => &mut self.right,
+        }
+    }
+}
+
+// Present when TickInput is written from outside the app, e.g. by the dedicated server,
+// instead of being sampled from this machine's keyboard, gamepads and pointer
+#[derive(Resource, Default)]
+pub struct ExternalInput;
+
+// FixedUpdate ticks since the match started. Replays and netcode index their inputs with it
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tick(pub u64);
+
+// Source of all gameplay randomness. Seeded, so the same seed and inputs give the same match
+#[derive(Resource, Clone)]
+pub struct GameRng {
+    pub seed: u64,
+    pub rng: StdRng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+// A fresh random seed, used for every match started from the menu
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(rand::random())
+    }
+}
+
+// Paddle returns since the last point. Every hits_per_speed_tier of them the ball goes up a
+// speed tier, until max_speed_tier
+#[derive(Resource, Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rally {
+    pub hits: u32,
+}
+
+impl Rally {
+    pub fn tier(&self, rules: &GameRules) -> u32 {
+        if rules.hits_per_speed_tier == 0 {
+            return 0;
+        }
+        (self.hits / rules.hits_per_speed_tier).min(rules.max_speed_tier)
+    }
+
+    // How many times ball_speed the ball goes at this tier
+    pub fn speed_multiplier(&self, rules: &GameRules) -> f32 {
+        1. + self.tier(rules) as f32 * rules.speed_per_tier
+    }
+}
+
+// Velocity the ball gets launched with on the next serve
+#[derive(Resource, Default, Clone)]
+pub struct Serve(pub Vec2);
+
+// Counts down the pauses between MatchState changes
+#[derive(Resource, Clone)]
+pub struct PhaseTimer(pub Timer);
+
+impl Default for PhaseTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(
+            GameRules::default().serve_delay,
+            TimerMode::Once,
+        ))
+    }
+}
+
 // ##############################################################
 // # Generic Components
 // ##############################################################
@@ -57,7 +293,7 @@ pub struct Score {
 pub struct Position(pub Vec2);
 
 // 2D "velocity" - effectively a measure of what direction all the systems want an object to go
-#[derive(Component, Default, Debug)]
+#[derive(Component, Default, Debug, Clone, Copy)]
 pub struct Velocity(pub Vec2);
 
 // Generic component holding the height and width of an entity's primitive mesh
@@ -68,11 +304,14 @@ pub struct Shape(pub Vec2);
 // # Entity Labels
 // ##############################################################
 
-// Player and AI Paddles
+// Player and AI Paddles, in local versus both paddles are Players
 #[derive(Component)]
 pub struct Ai;
 #[derive(Component)]
 pub struct Player;
+// Paddle steered by an external program, see the bot module
+#[derive(Component)]
+pub struct Bot;
 
 // Pong Ball
 #[derive(Component, Default)]
@@ -90,6 +329,17 @@ pub struct Gutter;
 #[derive(Component)]
 pub struct Scoreboard;
 
+// Rally speed tier display, under the scoreboard
+#[derive(Component)]
+pub struct SpeedTierText;
+
+// Selectable entry in one of the menus, index is its position from the top
+#[derive(Component)]
+pub struct MenuButton {
+    pub index: usize,
+    pub action: MenuAction,
+}
+
 // ##############################################################
 // # Entity Bundles
 // ##############################################################
@@ -97,27 +347,35 @@ pub struct Scoreboard;
 // Pong ball template
 #[derive(Bundle)]
 pub struct BallBundle {
-    pub ball: Ball,               // Label
-    pub shape: Shape,             // Dimensions of the sprite
-    pub position: Position,       // 2d position
-    pub velocity: LinearVelocity, // Sum of system forces/move direction
-    pub collider: Collider,       // Avian collider
-    pub rigidbody: RigidBody,     // Avian rigidbody
-    pub restitution: Restitution, // Avian collision elasticity
-    pub friction: Friction,       // Avian coefficient of friction
+    pub ball: Ball,                 // Label
+    pub shape: Shape,               // Dimensions of the sprite
+    pub position: Position,         // 2d position
+    pub velocity: LinearVelocity,   // Sum of system forces/move direction
+    pub spin: AngularVelocity,      // Picked up from moving paddles, curves the ball
+    pub ccd: SweptCcd,              // Stops it skipping through things at high speed
+    pub collider: Collider,         // Avian collider
+    pub layers: CollisionLayers,    // What it collides with
+    pub rigidbody: RigidBody,       // Avian rigidbody
+    pub restitution: Restitution,   // Avian collision elasticity
+    pub friction: Friction,         // Avian coefficient of friction
+    pub transform: TransformBundle, // Engine position
 }
 
 impl BallBundle {
-    pub fn new(x: f32, y: f32) -> Self {
+    pub fn new(x: f32, y: f32, size: f32) -> Self {
         Self {
             ball: Ball,
-            shape: Shape(Vec2::new(BALL_SIZE, BALL_SIZE)),
+            shape: Shape(Vec2::new(size, size)),
             velocity: LinearVelocity(Vec2::new(x, y)),
+            spin: AngularVelocity(0.),
+            ccd: SweptCcd::default(),
             position: Position(Vec2::ZERO),
-            collider: Collider::circle(BALL_SIZE),
+            collider: Collider::circle(size),
+            layers: CollisionLayers::new(Layer::Ball, [Layer::Paddle, Layer::Gutter]),
             rigidbody: RigidBody::Dynamic,
             restitution: Restitution::new(1.),
             friction: Friction::new(0.),
+            transform: TransformBundle::default(),
         }
     }
 }
@@ -126,26 +384,30 @@ impl BallBundle {
 #[derive(Bundle)]
 pub struct PaddleBundle {
     pub paddle: Paddle,
+    pub side: Side,
     pub shape: Shape,
     pub position: Position,
     pub velocity: Velocity,
     pub collider: Collider,
+    pub layers: CollisionLayers,
+    pub sensor: Sensor, // The physics reports ball contacts but leaves the response to reflect_ball
     pub rigidbody: RigidBody,
-    pub restitution: Restitution,
-    pub friction: Friction,
+    pub transform: TransformBundle,
 }
 
 impl PaddleBundle {
-    pub fn new(x: f32, y: f32) -> Self {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
         Self {
             paddle: Paddle,
-            shape: Shape(Vec2::new(PADDLE_WIDTH, PADDLE_HEIGHT)),
+            side: if x < 0. { Side::Left } else { Side::Right },
+            shape: Shape(Vec2::new(width, height)),
             position: Position(Vec2::new(x, y)),
             velocity: Velocity(Vec2::ZERO),
-            collider: Collider::rectangle(PADDLE_WIDTH, PADDLE_HEIGHT),
+            collider: Collider::rectangle(width, height),
+            layers: CollisionLayers::new(Layer::Paddle, Layer::Ball),
+            sensor: Sensor,
             rigidbody: RigidBody::Static,
-            restitution: Restitution::new(1.),
-            friction: Friction::new(0.),
+            transform: TransformBundle::from_transform(Transform::from_xyz(x, y, 0.)),
         }
     }
 }
@@ -157,21 +419,25 @@ pub struct GutterBundle {
     pub shape: Shape,
     pub position: Position,
     pub collider: Collider,
+    pub layers: CollisionLayers,
     pub rigidbody: RigidBody,
     pub restitution: Restitution,
     pub friction: Friction,
+    pub transform: TransformBundle,
 }
 
 impl GutterBundle {
-    pub fn new(x: f32, y: f32, width: f32) -> Self {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
         Self {
             gutter: Gutter,
-            shape: Shape(Vec2::new(width, GUTTER_HEIGHT)),
+            shape: Shape(Vec2::new(width, height)),
             position: Position(Vec2::new(x, y)),
-            collider: Collider::rectangle(width, GUTTER_HEIGHT),
+            collider: Collider::rectangle(width, height),
+            layers: CollisionLayers::new(Layer::Gutter, Layer::Ball),
             rigidbody: RigidBody::Static,
             restitution: Restitution::new(1.),
             friction: Friction::new(0.),
+            transform: TransformBundle::from_transform(Transform::from_xyz(x, y, 0.)),
         }
     }
 }
@@ -180,6 +446,16 @@ impl GutterBundle {
 // # Helper Functions
 // ##############################################################
 
+// Paddle velocity sent by something we don't control, like a bot or an agent. NaN gets through
+// a clamp, so anything that isn't a number counts as standing still
+pub fn clamp_movement(movement: f32) -> f32 {
+    if movement.is_finite() {
+        movement.clamp(-1., 1.)
+    } else {
+        0.
+    }
+}
+
 // https://easings.net is a great resource for these
 pub fn exp_easeout(t: f32, m: f32) -> f32 {
     // 1 - (1 - time)^magnitude
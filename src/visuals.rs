@@ -0,0 +1,147 @@
+use bevy::{
+    app::{App, Startup, Update},
+    asset::{AssetServer, Assets},
+    ecs::{
+        change_detection::ResMut,
+        query::{Added, Changed, Or, With},
+        schedule::IntoSystemConfigs,
+        system::{Commands, Query},
+    },
+    math::Vec2,
+    prelude::{
+        default, resource_changed, BuildChildren, Camera2dBundle, Children, Color, ColorMaterial,
+        Component, Entity, Image, Mesh, Plugin, Rectangle, Res, SpriteBundle, Transform,
+        VisibilityBundle, Window,
+    },
+    sprite::{Mesh2dHandle, Sprite},
+};
+
+use crate::{rules::GameRules, structure::*};
+
+// Everything that needs a window or GPU, left out of headless simulations
+pub struct PongVisualsPlugin;
+
+impl Plugin for PongVisualsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_camera);
+        app.add_systems(
+            Update,
+            (
+                add_ball_sprite,
+                add_rectangle_meshes,
+                resize_sprites,
+                resize_seams,
+                resize_meshes,
+                resize_window.run_if(resource_changed::<GameRules>),
+            ),
+        );
+    }
+}
+
+// Create scene camera
+pub fn spawn_camera(mut commands: Commands) {
+    commands.spawn_empty().insert(Camera2dBundle::default());
+}
+
+// Line across the ball so you can see it spin. It turns with the ball's rotation from the physics
+#[derive(Component)]
+struct BallSeam;
+
+// Give newly spawned balls their sprite, and a seam to show their spin
+fn add_ball_sprite(
+    mut commands: Commands,
+    balls: Query<(Entity, &Shape), Added<Ball>>,
+    asset_server: Res<AssetServer>,
+) {
+    for (entity, shape) in &balls {
+        commands.entity(entity).insert((
+            Sprite {
+                custom_size: Some(shape.0),
+                ..default()
+            },
+            asset_server.load::<Image>("ball.png"),
+            VisibilityBundle::default(),
+        ));
+        commands.entity(entity).with_children(|ball| {
+            ball.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::BLACK,
+                        custom_size: Some(seam_size(shape)),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(0., 0., 0.1),
+                    ..default()
+                },
+                BallSeam,
+            ));
+        });
+    }
+}
+
+// Seams stretch most of the way across the ball
+fn seam_size(shape: &Shape) -> Vec2 {
+    shape.0 * Vec2::new(0.8, 0.2)
+}
+
+// Give newly spawned paddles and gutters a white rectangle mesh the size of their Shape
+#[allow(clippy::type_complexity)]
+fn add_rectangle_meshes(
+    mut commands: Commands,
+    rectangles: Query<(Entity, &Shape), Or<(Added<Paddle>, Added<Gutter>)>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if rectangles.is_empty() {
+        return;
+    }
+
+    let material_handle = materials.add(ColorMaterial::from(Color::WHITE));
+
+    for (entity, shape) in &rectangles {
+        commands.entity(entity).insert((
+            Mesh2dHandle(meshes.add(Rectangle::from_size(shape.0))),
+            material_handle.clone(),
+            VisibilityBundle::default(),
+        ));
+    }
+}
+
+// Keep sprites matching their Shape when the rules resize them
+fn resize_sprites(mut sprites: Query<(&Shape, &mut Sprite), Changed<Shape>>) {
+    for (shape, mut sprite) in &mut sprites {
+        sprite.custom_size = Some(shape.0);
+    }
+}
+
+// Keep seams matching their ball when the rules resize it
+#[allow(clippy::type_complexity)]
+fn resize_seams(
+    balls: Query<(&Shape, &Children), (With<Ball>, Changed<Shape>)>,
+    mut seams: Query<&mut Sprite, With<BallSeam>>,
+) {
+    for (shape, children) in &balls {
+        for child in children {
+            if let Ok(mut sprite) = seams.get_mut(*child) {
+                sprite.custom_size = Some(seam_size(shape));
+            }
+        }
+    }
+}
+
+// Rebuild meshes when the rules resize their Shape
+fn resize_meshes(
+    mut rectangles: Query<(&Shape, &mut Mesh2dHandle), Changed<Shape>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (shape, mut mesh) in &mut rectangles {
+        *mesh = meshes.add(Rectangle::from_size(shape.0)).into();
+    }
+}
+
+// Keep the window the same size as the field
+fn resize_window(mut window: Query<&mut Window>, rules: Res<GameRules>) {
+    if let Ok(mut window) = window.get_single_mut() {
+        window.resolution.set(rules.field_width, rules.field_height);
+    }
+}
@@ -0,0 +1,158 @@
+// Shared by the integration tests: a headless match with nobody at the controls, that tests can
+// put the ball into, step one fixed tick at a time, and look at afterwards
+#![allow(dead_code)]
+
+use avian2d::prelude::{AngularVelocity, LinearVelocity};
+use bevy::{
+    ecs::event::{Events, ManualEventReader},
+    math::{Vec2, Vec3Swizzles},
+    prelude::{App, State, Transform, With},
+};
+use bevy_pong::{headless::headless_app, rules::GameRules, structure::*};
+
+pub struct TestMatch {
+    pub app: App,
+    pub scored: Vec<Scorer>, // Every ScoreEvent so far, in order
+    pub hits: Vec<Side>,     // Every PaddleHit so far, in order
+    pub won: Vec<Scorer>,    // Every MatchWon so far, in order
+    score_events: ManualEventReader<ScoreEvent>,
+    hit_events: ManualEventReader<PaddleHit>,
+    won_events: ManualEventReader<MatchWon>,
+}
+
+impl TestMatch {
+    // Local versus with ExternalInput, so the paddles only move when a test says so
+    pub fn new(rules: GameRules) -> Self {
+        let mut app = headless_app(rules, GameMode::LocalVersus, 0);
+        app.insert_resource(ExternalInput);
+
+        let mut test = Self {
+            app,
+            scored: Vec::new(),
+            hits: Vec::new(),
+            won: Vec::new(),
+            score_events: ManualEventReader::default(),
+            hit_events: ManualEventReader::default(),
+            won_events: ManualEventReader::default(),
+        };
+        // Enter the match and spawn everything
+        test.advance(1);
+        test
+    }
+
+    // Skip the serve delay and put the ball in play
+    pub fn serve(&mut self) {
+        self.app.world_mut().resource_mut::<TickInput>().right.serve = true;
+        self.advance(1);
+        assert_eq!(self.state(), MatchState::Playing);
+    }
+
+    // Move the ball somewhere and send it off with a velocity, replacing whatever it was doing
+    pub fn place_ball(&mut self, position: Vec2, velocity: Vec2) {
+        let world = self.app.world_mut();
+        let mut ball = world.query_filtered::<(&mut Transform, &mut LinearVelocity), With<Ball>>();
+        let (mut transform, mut linear_velocity) = ball.single_mut(world);
+
+        // Only the transform, avian adds how far it moved onto the physics position
+        transform.translation = position.extend(0.);
+        linear_velocity.0 = velocity;
+    }
+
+    // Set how fast the ball spins, in radians per second anticlockwise
+    pub fn spin_ball(&mut self, spin: f32) {
+        let world = self.app.world_mut();
+        let mut ball = world.query_filtered::<&mut AngularVelocity, With<Ball>>();
+        ball.single_mut(world).0 = spin;
+    }
+
+    // Hold a paddle's movement at a velocity between -1 and 1 until told otherwise
+    pub fn set_movement(&mut self, side: Side, movement: f32) {
+        self.app
+            .world_mut()
+            .resource_mut::<TickInput>()
+            .side_mut(side)
+            .movement = movement;
+    }
+
+    // Run ticks fixed ticks, collecting the events they send
+    pub fn advance(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.app.update();
+
+            let world = self.app.world();
+            self.scored.extend(
+                self.score_events
+                    .read(world.resource::<Events<ScoreEvent>>())
+                    .map(|ScoreEvent(scorer)| *scorer),
+            );
+            self.hits.extend(
+                self.hit_events
+                    .read(world.resource::<Events<PaddleHit>>())
+                    .map(|PaddleHit(side)| *side),
+            );
+            self.won.extend(
+                self.won_events
+                    .read(world.resource::<Events<MatchWon>>())
+                    .map(|MatchWon(winner)| *winner),
+            );
+        }
+    }
+
+    // Step until the condition holds, failing the test if it takes longer than max_ticks
+    pub fn advance_until(&mut self, max_ticks: u32, mut condition: impl FnMut(&mut Self) -> bool) {
+        for _ in 0..max_ticks {
+            if condition(self) {
+                return;
+            }
+            self.advance(1);
+        }
+        assert!(
+            condition(self),
+            "condition not met within {max_ticks} ticks"
+        );
+    }
+
+    pub fn rules(&self) -> &GameRules {
+        self.app.world().resource::<GameRules>()
+    }
+
+    pub fn score(&self) -> Score {
+        self.app.world().resource::<Score>().clone()
+    }
+
+    pub fn rally(&self) -> Rally {
+        *self.app.world().resource::<Rally>()
+    }
+
+    pub fn state(&self) -> MatchState {
+        *self.app.world().resource::<State<MatchState>>().get()
+    }
+
+    pub fn ball_position(&mut self) -> Vec2 {
+        let world = self.app.world_mut();
+        let mut ball = world.query_filtered::<&Transform, With<Ball>>();
+        ball.single(world).translation.xy()
+    }
+
+    pub fn ball_velocity(&mut self) -> Vec2 {
+        let world = self.app.world_mut();
+        let mut ball = world.query_filtered::<&LinearVelocity, With<Ball>>();
+        ball.single(world).0
+    }
+
+    pub fn ball_spin(&mut self) -> f32 {
+        let world = self.app.world_mut();
+        let mut ball = world.query_filtered::<&AngularVelocity, With<Ball>>();
+        ball.single(world).0
+    }
+
+    pub fn paddle_position(&mut self, side: Side) -> Vec2 {
+        let world = self.app.world_mut();
+        let mut paddles = world.query_filtered::<(&Transform, &Side), With<Paddle>>();
+        paddles
+            .iter(world)
+            .find(|(_, paddle_side)| **paddle_side == side)
+            .map(|(transform, _)| transform.translation.xy())
+            .expect("both paddles are spawned")
+    }
+}
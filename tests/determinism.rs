@@ -0,0 +1,108 @@
+// Matches from the same seed and inputs have to play out exactly the same, replays and rollback
+// netcode are built on it
+
+use avian2d::prelude::{AngularVelocity, LinearVelocity};
+use bevy::{
+    app::App,
+    ecs::event::{Events, ManualEventReader},
+    math::Vec2,
+    prelude::{State, Transform, With},
+};
+use bevy_pong::{ai::AiPersonality, headless::headless_app, rules::GameRules, structure::*};
+
+// Everything about a match that can be seen, with floats compared bit for bit
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    tick: u64,
+    score: Score,
+    rally: Rally,
+    phase: MatchState,
+    ball: [u32; 5],
+    paddles: Vec<(Side, [u32; 2])>,
+}
+
+fn bits(value: Vec2) -> [u32; 2] {
+    [value.x.to_bits(), value.y.to_bits()]
+}
+
+fn observe(app: &mut App) -> Snapshot {
+    let world = app.world_mut();
+
+    let mut ball =
+        world.query_filtered::<(&Transform, &LinearVelocity, &AngularVelocity), With<Ball>>();
+    let (transform, velocity, spin) = ball.single(world);
+    let [x, y] = bits(transform.translation.truncate());
+    let [vx, vy] = bits(velocity.0);
+    let ball = [x, y, vx, vy, spin.0.to_bits()];
+
+    let mut paddles = world.query_filtered::<(&Transform, &Side), With<Paddle>>();
+    let mut paddles: Vec<_> = paddles
+        .iter(world)
+        .map(|(transform, side)| (*side, bits(transform.translation.truncate())))
+        .collect();
+    paddles.sort_by_key(|(side, _)| *side == Side::Right);
+
+    Snapshot {
+        tick: world.resource::<Tick>().0,
+        score: world.resource::<Score>().clone(),
+        rally: *world.resource::<Rally>(),
+        phase: *world.resource::<State<MatchState>>().get(),
+        ball,
+        paddles,
+    }
+}
+
+// Seeded AIs and whatever the paddles are told to do, which is the same for the same tick
+fn play(rules: &GameRules, mode: GameMode, seed: u64, ticks: u32) -> (Snapshot, Vec<Scorer>) {
+    let mut app = headless_app(rules.clone(), mode, seed);
+    let scripted = mode == GameMode::LocalVersus;
+    if scripted {
+        app.insert_resource(ExternalInput);
+    }
+
+    let mut scored = Vec::new();
+    let mut reader = ManualEventReader::<ScoreEvent>::default();
+    for tick in 0..ticks {
+        if scripted {
+            let mut input = app.world_mut().resource_mut::<TickInput>();
+            input.left.movement = ((tick as f32) * 0.05).sin();
+            input.right.movement = ((tick as f32) * 0.031 + 1.).cos();
+            input.right.serve = tick % 300 == 0;
+        }
+        app.update();
+
+        let events = app.world().resource::<Events<ScoreEvent>>();
+        scored.extend(reader.read(events).map(|ScoreEvent(scorer)| *scorer));
+    }
+
+    (observe(&mut app), scored)
+}
+
+const TICKS: u32 = 64 * 60;
+
+#[test]
+fn same_seed_and_ai_play_the_same_match() {
+    for personality in AiPersonality::ALL {
+        let rules = GameRules {
+            ai_personality: personality,
+            ..GameRules::default()
+        };
+        let (first, first_points) = play(&rules, GameMode::AiVersusAi, 7, TICKS);
+        let (second, second_points) = play(&rules, GameMode::AiVersusAi, 7, TICKS);
+
+        assert_eq!(first, second, "{personality:?} matches differ");
+        assert_eq!(first_points, second_points, "{personality:?} points differ");
+    }
+}
+
+#[test]
+fn same_seed_and_inputs_play_the_same_match() {
+    let rules = GameRules::default();
+    let (first, first_points) = play(&rules, GameMode::LocalVersus, 99, TICKS);
+    let (second, second_points) = play(&rules, GameMode::LocalVersus, 99, TICKS);
+
+    assert_eq!(first.tick, second.tick);
+    assert!(first.tick > 0);
+    assert_eq!(first, second);
+    assert_eq!(first_points, second_points);
+}
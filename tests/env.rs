@@ -0,0 +1,21 @@
+// Agents stepping a PongEnv with actions that aren't numbers
+
+use bevy_pong::{env::PongEnv, rules::GameRules, structure::*};
+
+#[test]
+fn actions_that_arent_numbers_stand_still() {
+    let mut env = PongEnv::new(GameRules::default(), GameMode::LocalVersus);
+    let start = env.reset(3);
+
+    for action in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+        let (observation, _, _) = env.step(action, action);
+        assert!(observation.to_array().iter().all(|value| value.is_finite()));
+        assert_eq!(observation.left_paddle, start.left_paddle);
+        assert_eq!(observation.right_paddle, start.right_paddle);
+    }
+
+    // And the match carries on as normal afterwards
+    let (observation, _, _) = env.step(1., -1.);
+    assert!(observation.left_paddle.y > start.left_paddle.y);
+    assert!(observation.right_paddle.y < start.right_paddle.y);
+}
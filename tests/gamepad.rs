@@ -0,0 +1,114 @@
+// Gamepad control, driven by the same synthetic events a real pad's driver would send
+
+use bevy::{
+    app::App,
+    input::gamepad::{
+        Gamepad, GamepadAxisChangedEvent, GamepadAxisType, GamepadConnection,
+        GamepadConnectionEvent, GamepadEvent, GamepadInfo,
+    },
+    prelude::{Transform, With},
+};
+use bevy_pong::{controls::InputMap, headless::headless_app, rules::GameRules, structure::*};
+
+const PAD: Gamepad = Gamepad { id: 0 };
+
+// A local versus match with a pad plugged in, and its input sampled like the windowed game's
+fn match_with_pad() -> App {
+    let mut app = headless_app(GameRules::default(), GameMode::LocalVersus, 0);
+    app.world_mut()
+        .send_event(GamepadEvent::Connection(GamepadConnectionEvent::new(
+            PAD,
+            GamepadConnection::Connected(GamepadInfo {
+                name: String::from("Test pad"),
+            }),
+        )));
+    app.update();
+    app
+}
+
+fn tilt_stick(app: &mut App, deflection: f32) {
+    app.world_mut()
+        .send_event(GamepadEvent::Axis(GamepadAxisChangedEvent::new(
+            PAD,
+            GamepadAxisType::LeftStickY,
+            deflection,
+        )));
+}
+
+fn right_paddle_y(app: &mut App) -> f32 {
+    let world = app.world_mut();
+    let mut paddles = world.query_filtered::<(&Transform, &Side), With<Paddle>>();
+    paddles
+        .iter(world)
+        .find(|(_, side)| **side == Side::Right)
+        .map(|(transform, _)| transform.translation.y)
+        .expect("both paddles are spawned")
+}
+
+fn tick(app: &App) -> u64 {
+    app.world().resource::<Tick>().0
+}
+
+#[test]
+fn first_pad_plugged_in_steers_the_right_paddle_through_the_stick_curve() {
+    let mut app = match_with_pad();
+    tilt_stick(&mut app, 0.6);
+    app.update();
+
+    let expected = InputMap::default().stick.response(0.6);
+    let sampled = app.world().resource::<TickInput>().right.movement;
+    assert!(expected > 0. && expected < 1.);
+    assert!(
+        (sampled - expected).abs() < 1e-6,
+        "sampled {sampled}, expected {expected}"
+    );
+
+    // The stick stays where it was left, so the paddle keeps moving at that speed
+    let (start_y, start_tick) = (right_paddle_y(&mut app), tick(&app));
+    for _ in 0..20 {
+        app.update();
+    }
+    let ticks = (tick(&app) - start_tick) as f32;
+    let moved = right_paddle_y(&mut app) - start_y;
+    let speed = app.world().resource::<GameRules>().paddle_speed;
+
+    assert!(ticks > 0.);
+    assert!(
+        (moved - expected * speed * ticks).abs() < 1e-3,
+        "moved {moved} in {ticks} ticks"
+    );
+}
+
+#[test]
+fn stick_inside_the_deadzone_leaves_the_paddle_still() {
+    let mut app = match_with_pad();
+    let deadzone = InputMap::default().stick.deadzone;
+    tilt_stick(&mut app, -deadzone * 0.8);
+    app.update();
+
+    let start_y = right_paddle_y(&mut app);
+    for _ in 0..20 {
+        app.update();
+    }
+
+    assert_eq!(app.world().resource::<TickInput>().right.movement, 0.);
+    assert_eq!(right_paddle_y(&mut app), start_y);
+}
+
+#[test]
+fn unplugged_pad_stops_steering() {
+    let mut app = match_with_pad();
+    tilt_stick(&mut app, 1.);
+    app.update();
+    assert_eq!(app.world().resource::<TickInput>().right.movement, 1.);
+
+    app.world_mut()
+        .send_event(GamepadEvent::Connection(GamepadConnectionEvent::new(
+            PAD,
+            GamepadConnection::Disconnected,
+        )));
+    app.update();
+    app.update();
+
+    assert_eq!(app.world().resource::<TickInput>().right.movement, 0.);
+}
@@ -0,0 +1,413 @@
+mod common;
+
+use bevy::math::Vec2;
+use bevy_pong::{rules::GameRules, structure::*};
+
+use common::TestMatch;
+
+// ##############################################################
+// # Scoring
+// ##############################################################
+
+#[test]
+fn right_side_scores_when_the_ball_leaves_the_left() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    test.place_ball(Vec2::new(-620., 200.), Vec2::new(-200., 50.));
+    test.advance_until(64, |test| !test.scored.is_empty());
+
+    assert_eq!(test.scored, vec![Scorer::Player]);
+    assert_eq!(test.score(), Score { ai: 0, player: 1 });
+    test.advance(1);
+    assert_eq!(test.state(), MatchState::PointScored);
+    assert_eq!(test.ball_position(), Vec2::ZERO);
+}
+
+#[test]
+fn left_side_scores_when_the_ball_leaves_the_right() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    test.place_ball(Vec2::new(620., -200.), Vec2::new(200., -50.));
+    test.advance_until(64, |test| !test.scored.is_empty());
+
+    assert_eq!(test.scored, vec![Scorer::Ai]);
+    assert_eq!(test.score(), Score { ai: 1, player: 0 });
+}
+
+#[test]
+fn no_points_are_scored_while_serving() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.place_ball(Vec2::new(620., 200.), Vec2::new(200., 50.));
+    test.advance(32);
+
+    assert!(test.scored.is_empty());
+    assert_eq!(test.score(), Score::default());
+}
+
+#[test]
+fn reaching_the_target_score_wins_the_match() {
+    let mut test = TestMatch::new(GameRules {
+        target_score: 1,
+        win_by_two: false,
+        ..GameRules::default()
+    });
+    test.serve();
+    test.place_ball(Vec2::new(-620., 200.), Vec2::new(-200., 50.));
+    test.advance_until(64, |test| !test.won.is_empty());
+
+    assert_eq!(test.won, vec![Scorer::Player]);
+    test.advance(1);
+    assert_eq!(test.state(), MatchState::GameOver);
+}
+
+// ##############################################################
+// # Gutters
+// ##############################################################
+
+#[test]
+fn ball_bounces_off_the_top_gutter() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    test.place_ball(Vec2::new(0., 300.), Vec2::new(200., 200.));
+    test.advance_until(64, |test| test.ball_velocity().y < 0.);
+
+    // Nothing is lost in the bounce, only the vertical direction changes
+    let velocity = test.ball_velocity();
+    assert!(
+        (velocity - Vec2::new(200., -200.)).length() < 1.,
+        "{velocity}"
+    );
+
+    let top = test.rules().field_height / 2. - test.rules().gutter_height;
+    assert!(test.ball_position().y < top);
+    assert!(test.hits.is_empty());
+}
+
+#[test]
+fn ball_bounces_off_the_bottom_gutter() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    test.place_ball(Vec2::new(0., -300.), Vec2::new(-200., -200.));
+    test.advance_until(64, |test| test.ball_velocity().y > 0.);
+
+    let velocity = test.ball_velocity();
+    assert!(
+        (velocity - Vec2::new(-200., 200.)).length() < 1.,
+        "{velocity}"
+    );
+
+    let bottom = -test.rules().field_height / 2. + test.rules().gutter_height;
+    assert!(test.ball_position().y > bottom);
+}
+
+// ##############################################################
+// # Paddle Hits
+// ##############################################################
+
+// Send the ball at a paddle from in front of it and return its velocity after the hit
+fn hit_paddle(side: Side, offset: f32) -> Vec2 {
+    let direction = match side {
+        Side::Left => -1.,
+        Side::Right => 1.,
+    };
+
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    test.place_ball(
+        Vec2::new(500. * direction, offset),
+        Vec2::new(200. * direction, 20.),
+    );
+    test.advance_until(128, |test| !test.hits.is_empty());
+
+    assert_eq!(test.hits, vec![side]);
+    let velocity = test.ball_velocity();
+    assert_eq!(velocity.x.signum(), -direction, "ball didn't turn around");
+
+    // Hits trade horizontal speed for vertical speed, keeping the total the same
+    let speed = test.rules().ball_speed;
+    assert!(velocity.y.abs() >= speed && velocity.y.abs() <= speed * 1.5);
+    assert!((velocity.x.abs() + velocity.y.abs() - speed * 2.).abs() < 0.01);
+
+    // And the ball keeps going the way it was sent
+    let position = test.ball_position();
+    test.advance(16);
+    assert!((test.ball_position().x - position.x) * direction < 0.);
+    velocity
+}
+
+#[test]
+fn right_paddle_returns_the_ball() {
+    hit_paddle(Side::Right, 0.);
+}
+
+#[test]
+fn left_paddle_returns_the_ball() {
+    hit_paddle(Side::Left, 0.);
+}
+
+#[test]
+fn hits_further_from_the_center_are_steeper() {
+    let center = hit_paddle(Side::Right, 0.);
+    let edge = hit_paddle(Side::Right, 15.);
+
+    assert!(edge.y.abs() > center.y.abs(), "{edge} vs {center}");
+}
+
+#[test]
+fn ball_bounces_off_a_paddles_end_cap() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    // Dropping onto the top of the right paddle
+    test.place_ball(Vec2::new(590., 80.), Vec2::new(20., -200.));
+    test.advance_until(64, |test| test.ball_velocity().y > 0.);
+
+    // Like a wall: straight back up, still heading out past the paddle, and not a return
+    let velocity = test.ball_velocity();
+    assert!(
+        (velocity - Vec2::new(20., 200.)).length() < 1.,
+        "{velocity}"
+    );
+    assert!(test.hits.is_empty());
+    assert!(test.ball_position().y > test.rules().paddle_height / 2.);
+}
+
+#[test]
+fn moving_paddles_knock_the_ball_off_their_end_caps() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    // The right paddle comes up underneath a slowly falling ball
+    test.set_movement(Side::Right, 1.);
+    test.place_ball(Vec2::new(590., 80.), Vec2::new(20., -50.));
+    test.advance_until(64, |test| test.ball_velocity().y > 0.);
+
+    // It leaves faster than the paddle so it doesn't get hit again, and no faster than the cap
+    // as seen from the paddle
+    let velocity = test.ball_velocity();
+    let speed = test.rules().ball_speed;
+    let paddle_velocity = Vec2::new(0., test.rules().paddle_speed * 64.);
+    assert!(velocity.y > paddle_velocity.y, "{velocity}");
+    assert!(
+        (velocity - paddle_velocity).length() <= speed * 1.5 + 0.01,
+        "{velocity}"
+    );
+}
+
+#[test]
+fn corner_hits_bounce_off_the_corner() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    // Straight at the top front corner of the right paddle, which is at (585, 25)
+    test.place_ball(Vec2::new(545., 65.), Vec2::new(150., -150.));
+    test.advance_until(64, |test| test.ball_velocity().x < 0.);
+
+    // The normal points back along the way it came, so that's where it goes
+    let velocity = test.ball_velocity();
+    assert!(
+        (velocity - Vec2::new(-150., 150.)).length() < 1.,
+        "{velocity}"
+    );
+    assert_eq!(test.hits, vec![Side::Right]);
+}
+
+// ##############################################################
+// # Rally Speed
+// ##############################################################
+
+// Return the ball off the right paddle's face partway through a rally
+fn return_ball(rules: GameRules, rally: Rally) -> TestMatch {
+    let mut test = TestMatch::new(rules);
+    test.serve();
+    *test.app.world_mut().resource_mut::<Rally>() = rally;
+    test.place_ball(Vec2::new(500., 0.), Vec2::new(200., 20.));
+    test.advance_until(128, |test| !test.hits.is_empty());
+    test
+}
+
+// Returns trade horizontal for vertical speed, so this is what's constant across them
+fn return_speed(test: &mut TestMatch) -> f32 {
+    let velocity = test.ball_velocity();
+    (velocity.x.abs() + velocity.y.abs()) / 2.
+}
+
+#[test]
+fn returns_speed_the_rally_up() {
+    let rules = GameRules {
+        hits_per_speed_tier: 1,
+        speed_per_tier: 0.5,
+        ..GameRules::default()
+    };
+    let mut test = return_ball(rules, Rally::default());
+
+    assert_eq!(test.rally(), Rally { hits: 1 });
+    assert!((return_speed(&mut test) - 300.).abs() < 0.01);
+}
+
+#[test]
+fn rallies_only_speed_up_every_few_hits() {
+    let mut test = return_ball(GameRules::default(), Rally::default());
+    assert_eq!(test.rally(), Rally { hits: 1 });
+    assert!((return_speed(&mut test) - 200.).abs() < 0.01);
+
+    let mut test = return_ball(GameRules::default(), Rally { hits: 1 });
+    assert_eq!(test.rally().tier(test.rules()), 1);
+    assert!((return_speed(&mut test) - 220.).abs() < 0.01);
+}
+
+#[test]
+fn rally_speed_stops_at_the_top_tier() {
+    let rules = GameRules {
+        hits_per_speed_tier: 1,
+        max_speed_tier: 3,
+        ..GameRules::default()
+    };
+    let mut test = return_ball(rules, Rally { hits: 100 });
+
+    assert_eq!(test.rally().tier(test.rules()), 3);
+    assert!((return_speed(&mut test) - 260.).abs() < 0.01);
+}
+
+#[test]
+fn points_end_the_rally() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    *test.app.world_mut().resource_mut::<Rally>() = Rally { hits: 7 };
+    test.place_ball(Vec2::new(-620., 200.), Vec2::new(-200., 50.));
+    test.advance_until(64, |test| !test.scored.is_empty());
+
+    assert_eq!(test.rally(), Rally::default());
+}
+
+#[test]
+fn rules_that_let_the_ball_skip_paddles_are_rejected() {
+    let rules = GameRules {
+        speed_per_tier: 1.,
+        max_speed_tier: 20,
+        ..GameRules::default()
+    };
+    assert!(rules.validate().is_err());
+    assert!(GameRules::default().validate().is_ok());
+}
+
+// ##############################################################
+// # Spin
+// ##############################################################
+
+// Hit the right paddle's face while it moves and return the ball's velocity and spin after
+fn hit_moving_paddle(movement: f32) -> (Vec2, f32) {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    test.set_movement(Side::Right, movement);
+    test.place_ball(Vec2::new(560., 0.), Vec2::new(200., 20.));
+    test.advance_until(64, |test| !test.hits.is_empty());
+    (test.ball_velocity(), test.ball_spin())
+}
+
+#[test]
+fn still_paddles_dont_spin_the_ball() {
+    let (_, spin) = hit_moving_paddle(0.);
+    assert_eq!(spin, 0.);
+}
+
+#[test]
+fn moving_paddles_spin_the_ball_their_way() {
+    // The right paddle touches the ball's right edge, so moving up turns it anticlockwise
+    let (up, up_spin) = hit_moving_paddle(0.5);
+    let (down, down_spin) = hit_moving_paddle(-0.5);
+
+    assert!(up_spin > 0., "{up_spin}");
+    assert!(down_spin < 0., "{down_spin}");
+    assert!(up.x < 0. && down.x < 0.);
+}
+
+#[test]
+fn paddles_held_against_a_gutter_dont_spin_the_ball() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.set_movement(Side::Right, 1.);
+    test.advance(200);
+    let paddle = test.paddle_position(Side::Right);
+    assert_eq!(paddle.y, test.rules().paddle_max_y());
+
+    // Still pushing up, but the paddle isn't going anywhere
+    test.serve();
+    test.place_ball(Vec2::new(560., paddle.y), Vec2::new(200., 20.));
+    test.advance_until(64, |test| !test.hits.is_empty());
+
+    assert_eq!(test.ball_spin(), 0.);
+    assert_eq!(test.paddle_position(Side::Right), paddle);
+}
+
+#[test]
+fn spinning_balls_curve_without_changing_speed() {
+    let mut test = TestMatch::new(GameRules {
+        magnus_curve: 0.05,
+        ..GameRules::default()
+    });
+    test.serve();
+    test.place_ball(Vec2::new(0., 0.), Vec2::new(200., 20.));
+    test.spin_ball(5.);
+    let speed = test.ball_velocity().length();
+    test.advance(32);
+
+    // Anticlockwise spin bends a ball heading right upwards
+    let velocity = test.ball_velocity();
+    assert!(velocity.y > 30., "{velocity}");
+    assert!((velocity.length() - speed).abs() < 0.5, "{velocity}");
+}
+
+#[test]
+fn spin_doesnt_curve_the_ball_by_default() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.serve();
+    test.place_ball(Vec2::new(0., 0.), Vec2::new(200., 20.));
+    test.spin_ball(5.);
+    test.advance(32);
+
+    let velocity = test.ball_velocity();
+    assert!(
+        (velocity - Vec2::new(200., 20.)).length() < 0.01,
+        "{velocity}"
+    );
+}
+
+// ##############################################################
+// # Paddle Movement
+// ##############################################################
+
+#[test]
+fn paddles_move_at_paddle_speed() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.set_movement(Side::Right, 1.);
+    test.set_movement(Side::Left, -0.5);
+    test.advance(10);
+
+    let speed = test.rules().paddle_speed;
+    assert_eq!(test.paddle_position(Side::Right).y, speed * 10.);
+    assert_eq!(test.paddle_position(Side::Left).y, -speed * 5.);
+}
+
+#[test]
+fn paddles_stop_at_the_gutters() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.set_movement(Side::Right, 1.);
+    test.set_movement(Side::Left, -1.);
+    test.advance(200);
+
+    let max_y = test.rules().paddle_max_y();
+    assert_eq!(test.paddle_position(Side::Right).y, max_y);
+    assert_eq!(test.paddle_position(Side::Left).y, -max_y);
+}
+
+#[test]
+fn paddles_can_leave_the_gutters_again() {
+    let mut test = TestMatch::new(GameRules::default());
+    test.set_movement(Side::Right, 1.);
+    test.advance(200);
+    let top = test.paddle_position(Side::Right).y;
+
+    test.set_movement(Side::Right, -1.);
+    test.advance(1);
+    assert_eq!(
+        test.paddle_position(Side::Right).y,
+        top - test.rules().paddle_speed
+    );
+}
@@ -0,0 +1,73 @@
+// Finding a match hosted by another game on the same machine
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::{app::App, prelude::NextState};
+use bevy_pong::{
+    headless::headless_app,
+    lan::{LanAdvertiser, LanBrowser},
+    netcode::{NetConfig, NetSession},
+    rules::GameRules,
+    structure::*,
+};
+
+// Sitting in the main menu, like a game that was just started
+fn menu_app() -> App {
+    let mut app = headless_app(GameRules::default(), GameMode::Online, 0);
+    app.world_mut()
+        .resource_mut::<NextState<AppState>>()
+        .reset();
+    app
+}
+
+#[test]
+fn hosted_matches_are_found_over_loopback() {
+    let mut host = menu_app();
+    host.insert_resource(NetSession::host(0, NetConfig::default()).unwrap());
+    let port = host.world().resource::<NetSession>().local_port().unwrap();
+
+    // Opened the way the LAN games screen does
+    let mut browser = menu_app();
+    browser.insert_resource(LanBrowser::bind());
+    assert_eq!(browser.world().resource::<LanBrowser>().error, None);
+
+    // Adverts go out once a second of real time
+    let start = Instant::now();
+    let found = loop {
+        host.update();
+        browser.update();
+
+        let games = &browser.world().resource::<LanBrowser>().games;
+        if let Some(game) = games.iter().find(|game| game.advert.port == port) {
+            break game.clone();
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "the hosted match was never advertised"
+        );
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    assert_eq!(found.addr.port(), port);
+    assert!(!found.advert.in_progress);
+    assert!(host.world().get_resource::<LanAdvertiser>().is_some());
+    assert!(browser.world().get_resource::<LanAdvertiser>().is_none());
+}
+
+#[test]
+fn only_hosts_advertise() {
+    let mut app = menu_app();
+    app.update();
+    assert!(app.world().get_resource::<LanAdvertiser>().is_none());
+
+    app.insert_resource(NetSession::host(0, NetConfig::default()).unwrap());
+    app.update();
+    assert!(app.world().get_resource::<LanAdvertiser>().is_some());
+
+    app.world_mut().remove_resource::<NetSession>();
+    app.update();
+    assert!(app.world().get_resource::<LanAdvertiser>().is_none());
+}
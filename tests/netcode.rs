@@ -0,0 +1,236 @@
+// Two games playing an online match with each other over loopback
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use avian2d::prelude::LinearVelocity;
+use bevy::{
+    app::App,
+    math::Vec2,
+    prelude::{NextState, State, Transform, With},
+};
+use bevy_pong::{
+    headless::headless_app,
+    netcode::{NetConfig, NetSession, NetStatus},
+    rules::GameRules,
+    structure::*,
+};
+
+// No input delay, so both sides are always guessing and rolling back
+fn config() -> NetConfig {
+    NetConfig {
+        input_delay: 0,
+        peer_timeout: Duration::from_millis(300),
+        ..NetConfig::default()
+    }
+}
+
+// Starts in the main menu like the real game, the session takes it in game once connected
+fn online_app(session: NetSession) -> App {
+    let mut app = headless_app(GameRules::default(), GameMode::Online, 0);
+    app.world_mut()
+        .resource_mut::<NextState<AppState>>()
+        .reset();
+    app.insert_resource(ExternalInput);
+    app.insert_resource(session);
+    app
+}
+
+fn connected_pair() -> (App, App) {
+    let mut host = online_app(NetSession::host(0, config()).unwrap());
+    let port = host.world().resource::<NetSession>().local_port().unwrap();
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut guest = online_app(NetSession::join(addr, config()).unwrap());
+
+    for _ in 0..200 {
+        guest.update();
+        host.update();
+        if running(&host) && running(&guest) {
+            return (host, guest);
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    panic!("the guest never joined");
+}
+
+fn running(app: &App) -> bool {
+    *app.world().resource::<State<AppState>>().get() == AppState::InGame
+        && app.world().resource::<NetSession>().status == NetStatus::Running
+}
+
+fn tick(app: &App) -> u64 {
+    app.world().resource::<Tick>().0
+}
+
+// Where everything is, bit for bit
+fn positions(app: &mut App) -> Vec<[u32; 4]> {
+    let world = app.world_mut();
+    let mut bodies = world.query::<(&Transform, Option<&LinearVelocity>)>();
+    let mut positions: Vec<[u32; 4]> = bodies
+        .iter(world)
+        .map(|(transform, velocity)| {
+            let velocity = velocity.map_or(Vec2::ZERO, |velocity| velocity.0);
+            [
+                transform.translation.x.to_bits(),
+                transform.translation.y.to_bits(),
+                velocity.x.to_bits(),
+                velocity.y.to_bits(),
+            ]
+        })
+        .collect();
+    positions.sort();
+    positions
+}
+
+#[test]
+fn peers_play_the_same_match() {
+    let (mut host, mut guest) = connected_pair();
+
+    // Each tick writes both paddles' inputs back, so start from nothing every frame
+    for frame in 0..64 * 10 {
+        let mut input = TickInput::default();
+        input.left.movement = (frame as f32 * 0.07).sin();
+        host.insert_resource(input);
+
+        let mut input = TickInput::default();
+        input.right.movement = (frame as f32 * 0.05).cos();
+        input.right.serve = frame % 120 == 0;
+        guest.insert_resource(input);
+
+        host.update();
+        guest.update();
+        thread::sleep(Duration::from_micros(200));
+    }
+
+    // Stand still until every input has arrived on both sides, then line the ticks up
+    for _ in 0..64 {
+        host.insert_resource(TickInput::default());
+        guest.insert_resource(TickInput::default());
+        host.update();
+        guest.update();
+        thread::sleep(Duration::from_micros(200));
+    }
+    for _ in 0..64 {
+        if tick(&host) == tick(&guest) {
+            break;
+        }
+        let behind = if tick(&host) < tick(&guest) {
+            &mut host
+        } else {
+            &mut guest
+        };
+        behind.insert_resource(TickInput::default());
+        behind.update();
+    }
+
+    assert!(tick(&host) > 64 * 5, "the match barely ran");
+    assert_eq!(tick(&host), tick(&guest));
+    assert!(running(&host) && running(&guest), "the peers desynced");
+    assert_eq!(
+        host.world().resource::<Score>(),
+        guest.world().resource::<Score>()
+    );
+    assert_eq!(positions(&mut host), positions(&mut guest));
+}
+
+#[test]
+fn a_vanished_peer_ends_the_match() {
+    let (mut host, guest) = connected_pair();
+    drop(guest);
+
+    let start = Instant::now();
+    while host.world().get_resource::<NetSession>().is_some() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "the host is still waiting for its peer"
+        );
+        host.update();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(
+        *host.world().resource::<State<AppState>>().get(),
+        AppState::MainMenu
+    );
+}
+
+#[test]
+fn unanswered_joins_give_up() {
+    // Takes the packets and never answers
+    let silent = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let mut app = online_app(NetSession::join(silent.local_addr().unwrap(), config()).unwrap());
+
+    let start = Instant::now();
+    while app.world().get_resource::<NetSession>().is_some() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "still waiting for a host that isn't there"
+        );
+        app.update();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(
+        *app.world().resource::<State<AppState>>().get(),
+        AppState::MainMenu
+    );
+}
+
+#[test]
+fn playing_here_stops_hosting() {
+    let mut app = online_app(NetSession::host(0, config()).unwrap());
+    app.update();
+    assert!(app.world().get_resource::<NetSession>().is_some());
+
+    app.world_mut()
+        .resource_mut::<NextState<AppState>>()
+        .set(AppState::InGame);
+    app.update();
+    assert!(app.world().get_resource::<NetSession>().is_none());
+}
+
+#[test]
+fn garbage_peer_inputs_are_cleaned_up() {
+    let mut host = online_app(NetSession::host(0, config()).unwrap());
+    let port = host.world().resource::<NetSession>().local_port().unwrap();
+
+    // Talk to the host by hand, with a Hello and then inputs no real game would send
+    let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    peer.send_to(&[0], (Ipv4Addr::LOCALHOST, port)).unwrap();
+    for _ in 0..100 {
+        host.update();
+        if running(&host) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(running(&host), "the host never let the peer in");
+
+    let mut packet = vec![2];
+    packet.extend_from_slice(&0u64.to_le_bytes()); // Nothing of the host's has arrived
+    packet.extend_from_slice(&0u64.to_le_bytes()); // Starting from the first tick
+    packet.push(32);
+    for tick in 0..32 {
+        let movement = [f32::NAN, 1e30, f32::NEG_INFINITY, -1e30][tick % 4];
+        packet.extend_from_slice(&movement.to_le_bytes());
+        packet.push(0);
+    }
+    packet.push(0); // No checksums
+    peer.send_to(&packet, (Ipv4Addr::LOCALHOST, port)).unwrap();
+
+    for _ in 0..40 {
+        host.update();
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let max_y = GameRules::default().paddle_max_y();
+    let world = host.world_mut();
+    let mut paddles = world.query_filtered::<&Transform, With<Paddle>>();
+    for transform in paddles.iter(world) {
+        assert!(transform.translation.is_finite());
+        assert!(transform.translation.y.abs() <= max_y);
+    }
+}
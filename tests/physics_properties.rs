@@ -0,0 +1,133 @@
+mod common;
+
+use bevy::math::Vec2;
+use bevy_pong::{actors::paddle_hit_velocity, rules::GameRules, structure::*};
+use proptest::prelude::*;
+
+use common::TestMatch;
+
+// Paddle inputs are held for this many ticks each, about a quarter of a second
+const TICKS_PER_INPUT: u32 = 16;
+
+// A ball somewhere between the paddles, heading off in any direction that isn't close to
+// straight up and down, at up to what a paddle hit can give it
+fn ball() -> impl Strategy<Value = (Vec2, Vec2)> {
+    let rules = GameRules::default();
+    let max_y = rules.play_height() / 2. - rules.ball_size * 2.;
+    let speed = rules.ball_speed;
+
+    (
+        -500f32..500.,
+        -max_y..max_y,
+        speed * 0.5..speed,
+        speed * 0.1..speed * 1.5,
+        any::<bool>(),
+        any::<bool>(),
+    )
+        .prop_map(|(x, y, speed_x, speed_y, left, down)| {
+            let direction = Vec2::new(if left { -1. } else { 1. }, if down { -1. } else { 1. });
+            (Vec2::new(x, y), Vec2::new(speed_x, speed_y) * direction)
+        })
+}
+
+// Movement for the left and right paddle, changing every TICKS_PER_INPUT ticks
+fn inputs() -> impl Strategy<Value = Vec<(f32, f32)>> {
+    prop::collection::vec((-1f32..=1., -1f32..=1.), 1..32)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    // Play a rally out until someone scores or the inputs run out, checking every tick that
+    // nothing got out of bounds, through a paddle or stuck
+    #[test]
+    fn rallies_stay_in_bounds((position, velocity) in ball(), inputs in inputs()) {
+        let mut test = TestMatch::new(GameRules::default());
+        test.serve();
+        test.place_ball(position, velocity);
+
+        let rules = test.rules().clone();
+        let inner_edge = rules.field_height / 2. - rules.gutter_height;
+        let goal_line = rules.field_width / 2.;
+
+        let mut last_ball = position;
+        let mut stopped_ticks = 0;
+
+        'rally: for (left, right) in inputs {
+            test.set_movement(Side::Left, left);
+            test.set_movement(Side::Right, right);
+
+            for _ in 0..TICKS_PER_INPUT {
+                test.advance(1);
+                if !test.scored.is_empty() {
+                    break 'rally;
+                }
+
+                let ball = test.ball_position();
+
+                // The only way out of the field is through a goal line
+                prop_assert!(
+                    ball.y.abs() < inner_edge,
+                    "ball went through a gutter at {ball}"
+                );
+                // Scoring sees where the ball was at the start of the tick, so it can be a tick's
+                // travel past the goal line before the point is given
+                prop_assert!(
+                    ball.x.abs() < goal_line + rules.ball_size,
+                    "ball left the field at {ball}"
+                );
+
+                for side in [Side::Left, Side::Right] {
+                    let paddle = test.paddle_position(side);
+                    prop_assert!(
+                        paddle.y.abs() <= rules.paddle_max_y(),
+                        "{side:?} paddle went into a gutter at {paddle}"
+                    );
+
+                    // Getting behind a paddle means going around its ends, never through it
+                    let behind = |x: f32| (x - paddle.x) * paddle.x.signum() > 0.;
+                    if !behind(last_ball.x) && behind(ball.x) {
+                        prop_assert!(
+                            (ball.y - paddle.y).abs() >= rules.paddle_height / 2.,
+                            "ball went through the {side:?} paddle from {last_ball} to {ball}, \
+                             paddle at {paddle}"
+                        );
+                    }
+                }
+
+                // unstick_ball should never let the ball sit still for more than a tick
+                if test.ball_velocity().length() < 1. {
+                    stopped_ticks += 1;
+                    prop_assert!(stopped_ticks < 2, "ball stuck at {ball}");
+                } else {
+                    stopped_ticks = 0;
+                }
+
+                last_ball = ball;
+            }
+        }
+    }
+
+    // Wherever the ball meets a paddle, even off the ends, it leaves with between one and one and
+    // a half times the ball speed vertically, and the same total speed
+    #[test]
+    fn paddle_hits_stay_within_speed_bounds(
+        dist_from_center in 0f32..100.,
+        direction in (-1000f32..1000., -1000f32..1000.),
+    ) {
+        let rules = GameRules::default();
+        let speed = rules.ball_speed;
+        let direction = Vec2::new(direction.0, direction.1);
+        let velocity = paddle_hit_velocity(dist_from_center, direction, &rules);
+
+        // A little slack for rounding at the ends of the range
+        let within = |value: f32, min: f32, max: f32| value >= min - 0.01 && value <= max + 0.01;
+        prop_assert!(within(velocity.y.abs(), speed, speed * 1.5), "{velocity}");
+        prop_assert!(within(velocity.x.abs(), speed * 0.5, speed), "{velocity}");
+        prop_assert!((velocity.x.abs() + velocity.y.abs() - speed * 2.).abs() < 0.01);
+
+        // The ball keeps the direction it bounced in
+        prop_assert_eq!(velocity.x.signum(), direction.x.signum());
+        prop_assert_eq!(velocity.y.signum(), direction.y.signum());
+    }
+}
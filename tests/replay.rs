@@ -0,0 +1,150 @@
+// Recording a match, saving it, loading it back and watching it play out the same way
+
+use avian2d::prelude::PhysicsPlugins;
+use bevy::{
+    hierarchy::HierarchyPlugin,
+    input::InputPlugin,
+    prelude::{App, FixedPostUpdate, MinimalPlugins, NextState, PluginGroup},
+    scene::SceneSpawner,
+    state::app::StatesPlugin,
+    time::{Time, TimeUpdateStrategy, Virtual},
+    transform::TransformPlugin,
+};
+use bevy_pong::{
+    headless::TICK,
+    menu::PongMenuPlugin,
+    replay::{Playback, Recorder, Replay},
+    rules::{GameRules, PongRulesPlugin},
+    structure::*,
+    visuals::PongVisualsPlugin,
+    PongGame,
+};
+
+// Short enough that nobody wins, since finished matches get saved over the last replay
+const TICKS: u32 = 64 * 15;
+
+// Like headless_app, but with recording and playback left in
+fn replay_app(rules: GameRules, mode: GameMode, seed: u64) -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        StatesPlugin,
+        InputPlugin,
+        TransformPlugin,
+        HierarchyPlugin,
+        PongGame
+            .build()
+            .disable::<PongRulesPlugin>()
+            .disable::<PongMenuPlugin>()
+            .disable::<PongVisualsPlugin>(),
+        PhysicsPlugins::new(FixedPostUpdate),
+    ))
+    .insert_resource(rules)
+    .insert_resource(mode)
+    .insert_resource(GameRng::new(seed))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(TICK));
+    app.init_resource::<SceneSpawner>();
+
+    app.world_mut()
+        .resource_mut::<NextState<AppState>>()
+        .set(AppState::InGame);
+    app.finish();
+    app.cleanup();
+    app
+}
+
+fn record_match() -> Replay {
+    let mut app = replay_app(GameRules::default(), GameMode::AiVersusAi, 11);
+    for _ in 0..TICKS {
+        app.update();
+    }
+
+    let replay = app.world().resource::<Recorder>().0.clone();
+    let replay = replay.expect("the match was recorded");
+    assert_eq!(app.world().resource::<Score>().winner(&replay.rules), None);
+    replay
+}
+
+// Play a replay back to its end, returning the first tick that didn't match the recording
+fn play_back(replay: Replay) -> Option<u64> {
+    let mut app = replay_app(replay.rules.clone(), replay.mode, replay.seed);
+    app.insert_resource(Playback::new(replay));
+
+    let end = app.world().resource::<Playback>().end_tick();
+    for _ in 0..TICKS * 2 {
+        if app.world().resource::<Tick>().0 >= end {
+            break;
+        }
+        app.update();
+    }
+    assert_eq!(app.world().resource::<Tick>().0, end);
+    app.world().resource::<Playback>().diverged_at
+}
+
+#[test]
+fn saved_replays_load_back_unchanged() {
+    let replay = record_match();
+    let path = std::env::temp_dir().join(format!("bevy-pong-{}.replay", std::process::id()));
+    let path = path.to_str().expect("temp dir is utf-8");
+
+    replay.save(path).unwrap();
+    let loaded = Replay::load(path);
+    let _ = std::fs::remove_file(path);
+
+    assert_eq!(loaded.unwrap(), replay);
+}
+
+#[test]
+fn loaded_replays_play_back_with_the_recorded_checksums() {
+    let replay = record_match();
+    assert!(replay.checksums.len() > 1);
+
+    let loaded = Replay::from_bytes(&replay.to_bytes().unwrap()).unwrap();
+    assert_eq!(play_back(loaded), None);
+}
+
+#[test]
+fn playing_back_different_inputs_is_caught() {
+    let mut replay = record_match();
+    for frame in &mut replay.frames[100..] {
+        frame.right.movement = 1.;
+    }
+
+    assert!(play_back(replay).is_some());
+}
+
+#[test]
+fn corrupt_lengths_are_rejected_without_allocating_them() {
+    let bytes = record_match().to_bytes().unwrap();
+
+    // The rules length comes right after the magic, seed, mode and checksum interval
+    let mut huge_rules = bytes.clone();
+    huge_rules[21..25].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(Replay::from_bytes(&huge_rules).is_err());
+
+    // The checksum count is the last length, so cutting the file short leaves too few for it
+    let truncated = &bytes[..bytes.len() - 4];
+    assert!(Replay::from_bytes(truncated).is_err());
+    assert!(Replay::from_bytes(&bytes[..10]).is_err());
+}
+
+#[test]
+fn leaving_a_sped_up_replay_goes_back_to_normal_speed() {
+    let replay = record_match();
+    let mut app = replay_app(replay.rules.clone(), replay.mode, replay.seed);
+    let mut playback = Playback::new(replay);
+    playback.speed = 4.;
+    app.insert_resource(playback);
+
+    app.update();
+    app.update();
+    assert_eq!(app.world().resource::<Time<Virtual>>().relative_speed(), 4.);
+
+    app.world_mut()
+        .resource_mut::<NextState<AppState>>()
+        .set(AppState::MainMenu);
+    app.update();
+
+    assert!(app.world().get_resource::<Playback>().is_none());
+    assert_eq!(app.world().resource::<Time<Virtual>>().relative_speed(), 1.);
+}
@@ -0,0 +1,100 @@
+// Connections between the dedicated server and its clients holding up against bad peers
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use bevy_pong::server::{ClientMessage, Connection};
+
+// A connection and the raw socket on the other end of it
+fn pair() -> (Connection, TcpStream) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let other = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    (Connection::new(stream).unwrap(), other)
+}
+
+// Keep reading until something arrives or the connection closes
+fn receive_for_a_while(connection: &mut Connection) -> Vec<ClientMessage> {
+    for _ in 0..100 {
+        let messages = connection.receive::<ClientMessage>();
+        if !messages.is_empty() || connection.closed {
+            return messages;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    Vec::new()
+}
+
+#[test]
+fn messages_arrive_whole() {
+    let (mut connection, mut other) = pair();
+    other
+        .write_all(b"List\nInput(movement: 0.5, serve: true)\n")
+        .unwrap();
+
+    let messages = receive_for_a_while(&mut connection);
+    assert!(matches!(messages[0], ClientMessage::List));
+    assert!(matches!(
+        messages[1],
+        ClientMessage::Input {
+            movement: 0.5,
+            serve: true
+        }
+    ));
+    assert!(!connection.closed);
+}
+
+#[test]
+fn endless_lines_close_the_connection() {
+    let (mut connection, other) = pair();
+
+    // Write from another thread, the connection stops reading once it gives up
+    let writer = thread::spawn(move || {
+        let mut other = other;
+        let chunk = [b'a'; 4096];
+        for _ in 0..64 {
+            if other.write_all(&chunk).is_err() {
+                break;
+            }
+        }
+    });
+
+    while !connection.closed {
+        assert!(connection.receive::<ClientMessage>().is_empty());
+        thread::sleep(Duration::from_millis(1));
+    }
+    drop(connection);
+    writer.join().unwrap();
+}
+
+#[test]
+fn a_full_send_buffer_is_waited_out() {
+    let (mut connection, mut other) = pair();
+
+    // Far more than the socket buffers hold, without reading any of it yet
+    let message = ClientMessage::Join("A".repeat(1000));
+    for _ in 0..500 {
+        connection.send(&message);
+    }
+    assert!(!connection.closed);
+
+    other
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let mut received = Vec::new();
+    let mut buffer = [0; 65536];
+    while received.iter().filter(|&&byte| byte == b'\n').count() < 500 {
+        match other.read(&mut buffer) {
+            Ok(read) => received.extend_from_slice(&buffer[..read]),
+            // Let the connection write the rest of its queue
+            Err(_) => {
+                connection.receive::<ClientMessage>();
+            }
+        }
+    }
+    assert!(!connection.closed);
+}